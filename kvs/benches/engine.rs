@@ -3,7 +3,8 @@ extern crate criterion;
 extern crate tempfile;
 
 use criterion::{BatchSize, Criterion, ParameterizedBenchmark};
-use kvs::{KvStore, KvsEngine, SledKvsEngine};
+use kvs::{Durability, KvStore, KvStoreOptions, KvsEngine, SledKvsEngine};
+use std::thread;
 use tempfile::TempDir;
 
 /// Makes the requested number of key-value entries
@@ -12,14 +13,9 @@ use tempfile::TempDir;
 ///
 /// - length - the number of key-value pairs to make
 fn generate_entries(length: usize) -> Vec<(String, String)> {
-    let mut entries = vec![(String::new(), String::new()); length];
-    for i in 0..length {
-        entries[i] = (
-            format!("key{}", i).to_owned(),
-            format!("value{}", i).to_owned(),
-        );
-    }
-    entries
+    (0..length)
+        .map(|i| (format!("key{}", i), format!("value{}", i)))
+        .collect()
 }
 
 /// Tests setting 50 values on the kvs and sled engines
@@ -33,7 +29,7 @@ fn write_benchmark(c: &mut Criterion) {
                     let dir = TempDir::new().unwrap();
                     KvStore::open(dir.path()).unwrap()
                 },
-                |mut store| {
+                |store| {
                     for (k, v) in param_list {
                         store.set(k.to_string(), v.to_string()).unwrap();
                     }
@@ -49,7 +45,7 @@ fn write_benchmark(c: &mut Criterion) {
                 let dir = TempDir::new().unwrap();
                 SledKvsEngine::open(dir.path()).unwrap()
             },
-            |mut store| {
+            |store| {
                 for (k, v) in param_list {
                     store.set(k.to_string(), v.to_string()).unwrap();
                 }
@@ -67,7 +63,7 @@ fn read_benchmark(c: &mut Criterion) {
         "kvs_read",
         move |b, param_list| {
             let dir = TempDir::new().unwrap();
-            let mut store = KvStore::open(dir.path()).unwrap();
+            let store = KvStore::open(dir.path()).unwrap();
             for (k, v) in param_list {
                 store.set(k.to_string(), v.to_string()).unwrap();
             }
@@ -78,9 +74,8 @@ fn read_benchmark(c: &mut Criterion) {
                         Some(val) => assert_eq!(val, v),
                         None => panic!("Failed to get key '{}'", k),
                     }
-                    match &store.get(format!("{}-bad", k)).unwrap() {
-                        Some(_) => panic!("Found non-existent key"),
-                        None => {}
+                    if store.get(format!("{}-bad", k)).unwrap().is_some() {
+                        panic!("Found non-existent key");
                     }
                 }
             })
@@ -89,7 +84,7 @@ fn read_benchmark(c: &mut Criterion) {
     )
     .with_function("sled_read", move |b, param_list| {
         let dir = TempDir::new().unwrap();
-        let mut store = SledKvsEngine::open(dir.path()).unwrap();
+        let store = SledKvsEngine::open(dir.path()).unwrap();
         for (k, v) in param_list {
             store.set(k.to_string(), v.to_string()).unwrap();
         }
@@ -100,9 +95,8 @@ fn read_benchmark(c: &mut Criterion) {
                     Some(val) => assert_eq!(val, v),
                     None => panic!("Failed to get key '{}'", k),
                 }
-                match &store.get(format!("{}-bad", k)).unwrap() {
-                    Some(_) => panic!("Found non-existent key"),
-                    None => {}
+                if store.get(format!("{}-bad", k)).unwrap().is_some() {
+                    panic!("Found non-existent key");
                 }
             }
         })
@@ -110,5 +104,51 @@ fn read_benchmark(c: &mut Criterion) {
     c.bench("read_benchmark", benchmark);
 }
 
-criterion_group!(benches, write_benchmark, read_benchmark);
+/// Tests setting 64 values on the kvs engine with every write flushed to disk, spread over a
+/// varying number of threads. Group commit lets concurrent writers share a flush, so more threads
+/// should finish sooner.
+fn group_commit_benchmark(c: &mut Criterion) {
+    let entries = generate_entries(64);
+    c.bench_function_over_inputs(
+        "kvs_group_commit",
+        move |b, &&threads| {
+            b.iter_batched(
+                || {
+                    let dir = TempDir::new().unwrap();
+                    let store = KvStoreOptions::new()
+                        .durability(Durability::EveryWrite)
+                        .open(dir.path())
+                        .unwrap();
+                    (dir, store)
+                },
+                |(_dir, store)| {
+                    let handles: Vec<_> = entries
+                        .chunks(entries.len() / threads)
+                        .map(|chunk| {
+                            let store = store.clone();
+                            let chunk = chunk.to_vec();
+                            thread::spawn(move || {
+                                for (k, v) in chunk {
+                                    store.set(k, v).unwrap();
+                                }
+                            })
+                        })
+                        .collect();
+                    for handle in handles {
+                        handle.join().unwrap();
+                    }
+                },
+                BatchSize::SmallInput,
+            )
+        },
+        &[1, 4, 16],
+    );
+}
+
+criterion_group!(
+    benches,
+    write_benchmark,
+    read_benchmark,
+    group_commit_benchmark
+);
 criterion_main!(benches);
@@ -3,8 +3,7 @@ extern crate kvs;
 extern crate stderrlog;
 extern crate structopt;
 
-use kvs::{KvsClient, KvsRequest, KvsResponse, Result};
-use std::net::SocketAddr;
+use kvs::{KvsAddr, KvsClient, Result};
 use std::process::exit;
 use structopt::StructOpt;
 
@@ -16,33 +15,18 @@ fn main() -> Result<()> {
         .init()
         .unwrap();
 
-    let response = match opts.cmd {
-        Command::Get { addr, key } => {
-            let client = KvsClient::new(addr);
-            client.send(KvsRequest::Get { key })
-        }
-        Command::Set { addr, key, value } => {
-            let client = KvsClient::new(addr);
-            client.send(KvsRequest::Set { key, value })
-        }
-        Command::Remove { addr, key } => {
-            let client = KvsClient::new(addr);
-            client.send(KvsRequest::Remove { key })
-        }
-    }?;
-    match response {
-        KvsResponse::Get { value } => match value {
+    let result = match opts.cmd {
+        Command::Get { addr, key } => KvsClient::new(addr).get(key).map(|value| match value {
             Some(value) => println!("{}", value),
-            None => {
-                println!("Key not found");
-            }
-        },
-        KvsResponse::Error { message } => {
-            eprintln!("{}", message);
-            exit(1);
-        }
-        _ => {}
+            None => println!("Key not found"),
+        }),
+        Command::Set { addr, key, value } => KvsClient::new(addr).set(key, value),
+        Command::Remove { addr, key } => KvsClient::new(addr).remove(key),
     };
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        exit(1);
+    }
     Ok(())
 }
 
@@ -60,20 +44,20 @@ pub enum Command {
     #[structopt(name = "get")]
     Get {
         #[structopt(long = "addr", default_value = r#"127.0.0.1:4000"#)]
-        addr: SocketAddr,
+        addr: KvsAddr,
         key: String,
     },
     #[structopt(name = "set")]
     Set {
         #[structopt(long = "addr", default_value = r#"127.0.0.1:4000"#)]
-        addr: SocketAddr,
+        addr: KvsAddr,
         key: String,
         value: String,
     },
     #[structopt(name = "rm")]
     Remove {
         #[structopt(long = "addr", default_value = r#"127.0.0.1:4000"#)]
-        addr: SocketAddr,
+        addr: KvsAddr,
         key: String,
     },
 }
@@ -2,14 +2,21 @@ extern crate kvs;
 
 #[macro_use]
 extern crate log;
+extern crate signal_hook;
 extern crate stderrlog;
 extern crate structopt;
 
-use kvs::{KvsError, KvsServer, KvStore, Result, SledKvsEngine};
-use std::env;
+use kvs::{
+    CompactionThreshold, Durability, KvStoreOptions, KvsAddr, KvsEngine, KvsError, KvsServer,
+    NaiveThreadPool, RayonThreadPool, Result, SharedQueueThreadPool, ShutdownHandle, SledKvsEngine,
+    ThreadPool,
+};
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
 use std::io::{Read, Write};
-use std::net::SocketAddr;
-use structopt::StructOpt;
+use std::time::Duration;
+use std::{env, process, thread};
+use structopt::{clap, StructOpt};
 
 
 const ENGINE_FILE: &str = ".engine";
@@ -18,6 +25,7 @@ const SLED_ENGINE: &str = "sled";
 
 fn main() -> Result<()> {
     let opts = Opts::from_args();
+    check_engine_flags(&opts);
     stderrlog::new()
         .quiet(opts.quiet)
         .verbosity(2)
@@ -27,20 +35,29 @@ fn main() -> Result<()> {
     info!("Version {}", env!("CARGO_PKG_VERSION"));
     info!("Bind address {}", opts.addr);
     info!("Engine {}", opts.engine_name);
+    info!("Durability {:?}", durability(&opts));
+    info!(
+        "Thread pool {} with {} threads",
+        opts.thread_pool,
+        threads(&opts)
+    );
 
     if !check_engine(&opts.engine_name)? {
         return Err(KvsError::EngineMismatchError);
     }
-    set_engine(&opts.engine_name)?;
+    if !opts.read_only {
+        set_engine(&opts.engine_name)?;
+    }
 
     match &opts.engine_name[..] {
         KVS_ENGINE => {
-            let engine = KvStore::new()?;
-            KvsServer::new(opts.addr, engine).serve()?;
+            let engine = store_options(&opts).open(&env::current_dir()?)?;
+            serve(&opts, engine)?;
         }
         SLED_ENGINE => {
-            let engine = SledKvsEngine::new()?;
-            KvsServer::new(opts.addr, engine).serve()?;
+            let engine =
+                SledKvsEngine::open_with_durability(&env::current_dir()?, durability(&opts))?;
+            serve(&opts, engine)?;
         }
         _ => {
             panic!("Disallowed engine type found");
@@ -52,8 +69,9 @@ fn main() -> Result<()> {
 #[derive(StructOpt)]
 #[structopt(name = "kvs-server")]
 struct Opts {
+    /// The address to listen on: `host:port`, or `unix:/path` for a Unix domain socket
     #[structopt(long = "addr", default_value = r#"127.0.0.1:4000"#)]
-    addr: SocketAddr,
+    addr: KvsAddr,
     #[structopt(
         long = "engine",
         default_value = r#"kvs"#,
@@ -62,6 +80,199 @@ struct Opts {
     engine_name: String,
     #[structopt(short = "q", long = "quiet")]
     quiet: bool,
+    /// How connections are spread over threads
+    #[structopt(
+        long = "thread-pool",
+        default_value = r#"shared-queue"#,
+        raw(possible_values = r#"&["naive", "shared-queue", "rayon"]"#)
+    )]
+    thread_pool: String,
+    /// The number of threads handling connections (the number of CPUs by default)
+    #[structopt(long = "threads", parse(try_from_str = "parse_threads"))]
+    threads: Option<u32>,
+    /// Serve connections as tasks on a tokio runtime with `--threads` threads instead of on the
+    /// thread pool (only over TCP)
+    #[cfg(feature = "async")]
+    #[structopt(long = "async")]
+    run_async: bool,
+    /// Compact the kvs log once this many bytes of it are overwritten or removed data
+    #[structopt(long = "compact-bytes", conflicts_with = "compact_ratio")]
+    compact_bytes: Option<u64>,
+    /// Compact the kvs log once this fraction of it is overwritten or removed data
+    #[structopt(long = "compact-ratio", parse(try_from_str = "parse_compact_ratio"))]
+    compact_ratio: Option<f64>,
+    /// The size of each kvs log segment in bytes
+    #[structopt(long = "segment-bytes")]
+    segment_bytes: Option<u64>,
+    /// When writes are flushed to disk: never, before every reply, or in batches
+    #[structopt(
+        long = "durability",
+        default_value = r#"every-write"#,
+        raw(possible_values = r#"&["none", "every-write", "group-commit"]"#)
+    )]
+    durability: String,
+    /// How long a group commit may hold back writes, in milliseconds
+    #[structopt(
+        long = "group-commit-ms",
+        default_value = "10",
+        parse(try_from_str = "parse_group_commit_ms")
+    )]
+    group_commit_ms: u64,
+    /// Serve the kvs store without ever writing to it
+    #[structopt(long = "read-only")]
+    read_only: bool,
+    /// Fail instead of creating a new kvs store if there is none yet
+    #[structopt(long = "no-create")]
+    no_create: bool,
+    /// Fail if there already is a kvs store
+    #[structopt(long = "error-if-exists")]
+    error_if_exists: bool,
+    /// How long requests in flight get to finish after SIGINT or SIGTERM, in milliseconds
+    #[structopt(long = "shutdown-timeout-ms", default_value = "5000")]
+    shutdown_timeout_ms: u64,
+    /// How long a connection may wait for its next request before it is closed, in
+    /// milliseconds (0 keeps idle connections open)
+    #[structopt(long = "idle-timeout-ms", default_value = "5000")]
+    idle_timeout_ms: u64,
+}
+
+fn serve<E: KvsEngine>(opts: &Opts, engine: E) -> Result<()> {
+    let threads = threads(opts);
+    #[cfg(feature = "async")]
+    {
+        if opts.run_async {
+            let addr = match &opts.addr {
+                KvsAddr::Tcp(addr) => *addr,
+                #[allow(unreachable_patterns)]
+                addr => {
+                    return Err(KvsError::BadAddressError(format!(
+                        "{} (--async only serves TCP addresses)",
+                        addr
+                    )))
+                }
+            };
+            let server = kvs::AsyncKvsServer::bind(addr, engine)?;
+            info!("Listening on {}", server.local_addr());
+            shut_down_on_signal(opts, server.shutdown_handle())?;
+            return tokio::runtime::Builder::new_multi_thread()
+                .worker_threads(threads as usize)
+                .enable_all()
+                .build()?
+                .block_on(server.serve());
+        }
+    }
+    match &opts.thread_pool[..] {
+        "naive" => run(opts, engine, NaiveThreadPool::new(threads)?),
+        "rayon" => run(opts, engine, RayonThreadPool::new(threads)?),
+        _ => run(opts, engine, SharedQueueThreadPool::new(threads)?),
+    }
+}
+
+/// Serves until SIGINT or SIGTERM shuts the server down
+fn run<E: KvsEngine, P: ThreadPool>(opts: &Opts, engine: E, pool: P) -> Result<()> {
+    let mut server = KvsServer::bind(opts.addr.clone(), engine, pool)?;
+    server.idle_timeout(match opts.idle_timeout_ms {
+        0 => None,
+        ms => Some(Duration::from_millis(ms)),
+    });
+    info!("Listening on {}", server.local_addr());
+    shut_down_on_signal(opts, server.shutdown_handle())?;
+    server.serve()
+}
+
+/// Shuts the server down on SIGINT or SIGTERM. A second signal exits straight away.
+fn shut_down_on_signal(opts: &Opts, handle: ShutdownHandle) -> Result<()> {
+    let grace = Duration::from_millis(opts.shutdown_timeout_ms);
+    let mut signals = Signals::new([SIGINT, SIGTERM])?;
+    thread::spawn(move || {
+        let mut signals = signals.forever();
+        if let Some(signal) = signals.next() {
+            info!("Received signal {}", signal);
+            handle.shutdown(grace);
+        }
+        if signals.next().is_some() {
+            warn!("Received another signal, exiting without waiting");
+            process::exit(1);
+        }
+    });
+    Ok(())
+}
+
+fn parse_threads(threads: &str) -> std::result::Result<u32, String> {
+    match threads.parse() {
+        Ok(0) => Err("at least one thread is needed".to_owned()),
+        Ok(threads) => Ok(threads),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+fn parse_compact_ratio(ratio: &str) -> std::result::Result<f64, String> {
+    match ratio.parse() {
+        Ok(ratio) if ratio > 0.0 && ratio <= 1.0 => Ok(ratio),
+        Ok(_) => Err("the ratio must be above 0 and at most 1".to_owned()),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+fn parse_group_commit_ms(ms: &str) -> std::result::Result<u64, String> {
+    match ms.parse() {
+        Ok(0) => Err("a group commit needs an interval of at least 1ms".to_owned()),
+        Ok(ms) => Ok(ms),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+fn threads(opts: &Opts) -> u32 {
+    opts.threads.unwrap_or_else(|| num_cpus::get() as u32)
+}
+
+/// Exits with a usage error if a flag that only the kvs engine understands is given for sled
+fn check_engine_flags(opts: &Opts) {
+    if opts.engine_name != SLED_ENGINE {
+        return;
+    }
+    let kvs_only = [
+        ("--compact-bytes", opts.compact_bytes.is_some()),
+        ("--compact-ratio", opts.compact_ratio.is_some()),
+        ("--segment-bytes", opts.segment_bytes.is_some()),
+        ("--read-only", opts.read_only),
+        ("--no-create", opts.no_create),
+        ("--error-if-exists", opts.error_if_exists),
+    ];
+    if let Some((flag, _)) = kvs_only.iter().find(|(_, given)| *given) {
+        clap::Error::with_description(
+            &format!("{} is only supported by the kvs engine", flag),
+            clap::ErrorKind::ArgumentConflict,
+        )
+        .exit();
+    }
+}
+
+fn store_options(opts: &Opts) -> KvStoreOptions {
+    let mut options = KvStoreOptions::new();
+    if let Some(bytes) = opts.compact_bytes {
+        options.compaction_threshold(CompactionThreshold::Bytes(bytes));
+    }
+    if let Some(ratio) = opts.compact_ratio {
+        options.compaction_threshold(CompactionThreshold::StaleRatio(ratio));
+    }
+    if let Some(segment_bytes) = opts.segment_bytes {
+        options.segment_bytes(segment_bytes);
+    }
+    options
+        .durability(durability(opts))
+        .read_only(opts.read_only)
+        .create_if_missing(!opts.no_create)
+        .error_if_exists(opts.error_if_exists);
+    options
+}
+
+fn durability(opts: &Opts) -> Durability {
+    match &opts.durability[..] {
+        "none" => Durability::None,
+        "group-commit" => Durability::GroupCommit(Duration::from_millis(opts.group_commit_ms)),
+        _ => Durability::EveryWrite,
+    }
 }
 
 fn check_engine(engine: &str) -> Result<bool> {
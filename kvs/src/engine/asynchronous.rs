@@ -0,0 +1,64 @@
+use crate::{KvsEngine, KvsError, Result};
+use std::future::Future;
+use tokio::task;
+
+/// Adapts a `KvsEngine` for use from async code
+///
+/// Engine calls block on disk I/O (and on locks within the engine), so every call is moved to
+/// tokio's blocking thread pool with a handle of its own to the engine, leaving the runtime's
+/// worker threads free to drive connections. The returned futures do not borrow the adapter, so
+/// they can be sent to other tasks even though engine handles are not `Sync`. Clones share the
+/// same engine.
+#[derive(Clone)]
+pub struct AsyncKvsEngine<E: KvsEngine> {
+    engine: E,
+}
+
+impl<E: KvsEngine> AsyncKvsEngine<E> {
+    /// Wraps `engine` for use from async code
+    pub fn new(engine: E) -> AsyncKvsEngine<E> {
+        AsyncKvsEngine { engine }
+    }
+
+    /// Retrieves the value for a given key. See `KvsEngine::get`.
+    pub fn get(&self, key: String) -> impl Future<Output = Result<Option<String>>> {
+        self.run(move |engine| engine.get(key))
+    }
+
+    /// Sets a value for a given key. See `KvsEngine::set`.
+    pub fn set(&self, key: String, value: String) -> impl Future<Output = Result<()>> {
+        self.run(move |engine| engine.set(key, value))
+    }
+
+    /// Removes a key-value relationship. See `KvsEngine::remove`.
+    ///
+    /// # Errors
+    ///
+    /// A `KvsError::BadRemovalError` will occur if the requested key was not found
+    pub fn remove(&self, key: String) -> impl Future<Output = Result<()>> {
+        self.run(move |engine| engine.remove(key))
+    }
+
+    /// Flushes every change accepted so far to disk. See `KvsEngine::flush`.
+    pub fn flush(&self) -> impl Future<Output = Result<()>> {
+        self.run(|engine| engine.flush())
+    }
+
+    /// Gives up the adapter and returns the engine it wraps
+    pub fn into_inner(self) -> E {
+        self.engine
+    }
+
+    fn run<T, F>(&self, call: F) -> impl Future<Output = Result<T>>
+    where
+        T: Send + 'static,
+        F: FnOnce(&E) -> Result<T> + Send + 'static,
+    {
+        let engine = self.engine.clone();
+        async move {
+            task::spawn_blocking(move || call(&engine))
+                .await
+                .map_err(|err| KvsError::InternalError(format!("Engine call failed: {}", err)))?
+        }
+    }
+}
@@ -0,0 +1,72 @@
+use super::entry::LogEntry;
+use crate::{KvsError, Result};
+use std::sync::Mutex;
+use std::{collections, io};
+
+/// A change waiting to be appended to the log by whichever writer commits the next batch
+pub struct PendingWrite {
+    /// Identifies the caller waiting on the change
+    pub ticket: u64,
+    /// The change itself
+    pub entry: LogEntry,
+    /// The serialized change
+    pub payload: Vec<u8>,
+}
+
+/// Collects changes from concurrent writers so that they can be appended (and flushed to disk)
+/// together as a single batch
+///
+/// Every writer queues its change and then waits for the lock on the active segment. Whoever gets
+/// the lock first takes every change queued so far and commits all of them at once, while the
+/// others find their change already committed by the time they get the lock in turn.
+#[derive(Default)]
+pub struct CommitQueue {
+    state: Mutex<CommitState>,
+}
+
+#[derive(Default)]
+struct CommitState {
+    next_ticket: u64,
+    pending: Vec<PendingWrite>,
+    results: collections::HashMap<u64, Result<()>>,
+}
+
+impl CommitQueue {
+    /// Queues a change for the next batch, returning the ticket used to pick up its outcome
+    pub fn enqueue(&self, entry: LogEntry, payload: Vec<u8>) -> u64 {
+        let mut state = self.state.lock().unwrap();
+        let ticket = state.next_ticket;
+        state.next_ticket += 1;
+        state.pending.push(PendingWrite {
+            ticket,
+            entry,
+            payload,
+        });
+        ticket
+    }
+
+    /// Takes every queued change, in the order they were queued
+    pub fn drain(&self) -> Vec<PendingWrite> {
+        let mut state = self.state.lock().unwrap();
+        std::mem::take(&mut state.pending)
+    }
+
+    /// Records the outcome of a batch of changes for their callers to pick up
+    pub fn complete(&self, results: Vec<(u64, Result<()>)>) {
+        let mut state = self.state.lock().unwrap();
+        state.results.extend(results);
+    }
+
+    /// Picks up the outcome of the change with `ticket`, if it was committed already
+    pub fn take(&self, ticket: u64) -> Option<Result<()>> {
+        self.state.lock().unwrap().results.remove(&ticket)
+    }
+}
+
+/// Makes a copy of the error that failed a batch, for every change in the batch after the first
+pub fn batch_error(err: &KvsError) -> KvsError {
+    match err {
+        KvsError::IoError(err) => KvsError::IoError(io::Error::new(err.kind(), err.to_string())),
+        err => KvsError::InternalError(err.to_string()),
+    }
+}
@@ -0,0 +1,162 @@
+use super::kv::Shared;
+use crate::{KvsError, Result};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc};
+use std::{collections, thread};
+
+/// A snapshot of the state of background compaction in a `KvStore`
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CompactionProgress {
+    /// Whether a compaction is running right now
+    pub running: bool,
+    /// The number of live entries the running (or last) compaction has copied so far
+    pub entries_copied: u64,
+    /// The number of live entries the running (or last) compaction has to copy in total
+    pub entries_total: u64,
+    /// The number of compactions that have finished since the store was opened
+    pub completed: u64,
+    /// The number of compactions that have failed since the store was opened
+    pub failed: u64,
+}
+
+/// Counters the compaction thread updates as it works, readable from any thread
+#[derive(Default)]
+pub struct ProgressCounters {
+    running: AtomicBool,
+    entries_copied: AtomicU64,
+    entries_total: AtomicU64,
+    completed: AtomicU64,
+    failed: AtomicU64,
+}
+
+impl ProgressCounters {
+    /// Records that a compaction copying `entries_total` entries has started
+    pub fn start(&self, entries_total: u64) {
+        self.entries_copied.store(0, Ordering::SeqCst);
+        self.entries_total.store(entries_total, Ordering::SeqCst);
+        self.running.store(true, Ordering::SeqCst);
+    }
+
+    /// Records that one more entry has been copied into the compacted segment
+    pub fn copied(&self) {
+        self.entries_copied.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn finish(&self, result: &Result<()>) {
+        match result {
+            Ok(_) => self.completed.fetch_add(1, Ordering::SeqCst),
+            Err(_) => self.failed.fetch_add(1, Ordering::SeqCst),
+        };
+        self.running.store(false, Ordering::SeqCst);
+    }
+
+    fn snapshot(&self) -> CompactionProgress {
+        CompactionProgress {
+            running: self.running.load(Ordering::SeqCst),
+            entries_copied: self.entries_copied.load(Ordering::SeqCst),
+            entries_total: self.entries_total.load(Ordering::SeqCst),
+            completed: self.completed.load(Ordering::SeqCst),
+            failed: self.failed.load(Ordering::SeqCst),
+        }
+    }
+}
+
+enum Message {
+    /// Run a compaction, optionally reporting the outcome back to whoever asked for it
+    Compact(Option<mpsc::Sender<Result<()>>>),
+    /// Stop the compaction thread
+    Stop,
+}
+
+/// A handle to the background thread that compacts the sealed segments of a `KvStore`. Dropping
+/// the handle waits for a running compaction to finish and stops the thread.
+pub struct Compactor {
+    sender: mpsc::Sender<Message>,
+    pending: Arc<AtomicBool>,
+    progress: Arc<ProgressCounters>,
+    handle: Option<thread::JoinHandle<()>>,
+}
+
+impl Compactor {
+    /// Starts the compaction thread for the store described by `shared`
+    ///
+    /// # Errors
+    ///
+    /// A `KvsError::IoError` will occur if the thread cannot be started
+    pub fn spawn(shared: Arc<Shared>) -> Result<Compactor> {
+        let (sender, receiver) = mpsc::channel();
+        let pending = Arc::new(AtomicBool::new(false));
+        let progress = Arc::new(ProgressCounters::default());
+
+        let thread_pending = pending.clone();
+        let thread_progress = progress.clone();
+        let handle = thread::Builder::new()
+            .name("kvs-compaction".to_owned())
+            .spawn(move || {
+                let mut readers = collections::HashMap::new();
+                for message in receiver {
+                    let reply = match message {
+                        Message::Compact(reply) => reply,
+                        Message::Stop => break,
+                    };
+                    let result = shared.compact(&mut readers, &thread_progress);
+                    readers.clear();
+                    thread_progress.finish(&result);
+                    thread_pending.store(false, Ordering::SeqCst);
+                    match reply {
+                        Some(reply) => {
+                            let _ = reply.send(result);
+                        }
+                        None => {
+                            if let Err(err) = result {
+                                error!("Background compaction failed: {}", err);
+                            }
+                        }
+                    }
+                }
+            })?;
+
+        Ok(Compactor {
+            sender,
+            pending,
+            progress,
+            handle: Some(handle),
+        })
+    }
+
+    /// Asks the compaction thread to compact, unless a compaction is already waiting or running.
+    /// Returns immediately.
+    pub fn trigger(&self) {
+        if !self.pending.swap(true, Ordering::SeqCst) {
+            let _ = self.sender.send(Message::Compact(None));
+        }
+    }
+
+    /// Runs a compaction on the compaction thread and waits for it to finish
+    pub fn compact(&self) -> Result<()> {
+        let (reply, result) = mpsc::channel();
+        self.pending.store(true, Ordering::SeqCst);
+        self.sender
+            .send(Message::Compact(Some(reply)))
+            .map_err(|_| KvsError::InternalError("Compaction thread has stopped".to_owned()))?;
+        result
+            .recv()
+            .map_err(|_| KvsError::InternalError("Compaction thread has stopped".to_owned()))?
+    }
+
+    /// Reports what the compaction thread is doing
+    pub fn progress(&self) -> CompactionProgress {
+        self.progress.snapshot()
+    }
+}
+
+impl Drop for Compactor {
+    fn drop(&mut self) {
+        let _ = self.sender.send(Message::Stop);
+        if let Some(handle) = self.handle.take() {
+            if handle.join().is_err() {
+                error!("Compaction thread panicked");
+            }
+        }
+    }
+}
@@ -0,0 +1,37 @@
+use crate::Result;
+use bincode::{deserialize, serialize};
+use serde::{Deserialize, Serialize};
+
+/// A single change recorded in the `KvStore` log
+///
+/// This type is the on-disk format and is intentionally separate from `KvsRequest`, so that the
+/// wire protocol can change without touching existing data directories (and the other way round).
+/// Variants may only ever be appended; any other change requires a new segment format version
+/// along with an upgrade path for the old one.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum LogEntry {
+    /// A value was associated with a key
+    Set {
+        /// The key that was associated
+        key: String,
+        /// The value that was associated
+        value: String,
+    },
+    /// A key-value pair was removed
+    Remove {
+        /// The key that was removed
+        key: String,
+    },
+}
+
+impl LogEntry {
+    /// Serializes the entry into a record payload
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        Ok(serialize(self)?)
+    }
+
+    /// Deserializes an entry from a record payload
+    pub fn decode(payload: &[u8]) -> Result<LogEntry> {
+        Ok(deserialize(payload)?)
+    }
+}
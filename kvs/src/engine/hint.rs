@@ -0,0 +1,89 @@
+use super::segment::{checksum, segment_path, sync_dir, LogPointer};
+use crate::Result;
+use bincode::{deserialize, serialize};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::{collections, fs, path};
+
+/// The file name of the hint file describing the most recently compacted segment
+const HINTFILE: &str = "kvs.hint";
+/// The file name of the hint file while it is being written
+const HINTFILE_TMP: &str = "kvs.hint.tmp";
+/// The magic number that begins every hint file
+const HINT_MAGIC: [u8; 4] = *b"KVSH";
+/// The version of the hint file format written by this build
+const HINT_VERSION: u32 = 1;
+/// The size of the magic number, format version and checksum that begin every hint file
+const HINT_HEADER_BYTES: usize = 12;
+
+/// A snapshot of the index as it was right after a compaction, which lets `KvStore::open` skip
+/// replaying the compacted segment
+#[derive(Serialize, Deserialize)]
+pub struct Hint {
+    /// The id of the compacted segment the hint describes
+    pub segment: u64,
+    /// The size of the compacted segment, used to detect a hint that no longer matches it
+    pub size: u64,
+    /// The location of every live key within the compacted segment
+    pub entries: collections::HashMap<String, LogPointer>,
+}
+
+impl Hint {
+    /// Loads the hint file in `root`. Returns `None` if there is no hint file, or if it is damaged
+    /// or does not match the segment it describes, in which case the log has to be replayed in
+    /// full instead.
+    pub fn load(root: &path::Path) -> Result<Option<Hint>> {
+        let hint_path = root.join(HINTFILE);
+        if !hint_path.is_file() {
+            return Ok(None);
+        }
+
+        let content = fs::read(&hint_path)?;
+        let hint = match decode_hint(&content) {
+            Some(hint) => hint,
+            None => {
+                warn!("Ignoring damaged hint file {:?}", hint_path);
+                return Ok(None);
+            }
+        };
+        let size = fs::metadata(segment_path(root, hint.segment)).map(|metadata| metadata.len());
+        if size.ok() != Some(hint.size) {
+            warn!(
+                "Ignoring stale hint file for segment {} {:?}",
+                hint.segment, hint_path
+            );
+            return Ok(None);
+        }
+        Ok(Some(hint))
+    }
+
+    /// Writes the hint file in `root`, atomically replacing any previous one
+    pub fn store(&self, root: &path::Path) -> Result<()> {
+        let body = serialize(self)?;
+        let tmp_path = root.join(HINTFILE_TMP);
+        let mut hintfile = fs::File::create(&tmp_path)?;
+        hintfile.write_all(&HINT_MAGIC)?;
+        hintfile.write_all(&HINT_VERSION.to_le_bytes())?;
+        hintfile.write_all(&checksum(&body).to_le_bytes())?;
+        hintfile.write_all(&body)?;
+        hintfile.sync_all()?;
+        drop(hintfile);
+
+        fs::rename(tmp_path, root.join(HINTFILE))?;
+        sync_dir(root)?;
+        Ok(())
+    }
+}
+
+fn decode_hint(content: &[u8]) -> Option<Hint> {
+    if content.len() < HINT_HEADER_BYTES || content[..4] != HINT_MAGIC {
+        return None;
+    }
+    let version = u32::from_le_bytes([content[4], content[5], content[6], content[7]]);
+    let crc = u32::from_le_bytes([content[8], content[9], content[10], content[11]]);
+    let body = &content[HINT_HEADER_BYTES..];
+    if version != HINT_VERSION || checksum(body) != crc {
+        return None;
+    }
+    deserialize(body).ok()
+}
@@ -1,21 +1,89 @@
-use crate::{KvsError, KvsRequest, KvsEngine, Result};
-use serde_json::{from_str, to_string};
-use std::io::{BufRead, Seek, Write};
+use super::commit::{batch_error, CommitQueue, PendingWrite};
+use super::compactor::{CompactionProgress, Compactor, ProgressCounters};
+use super::entry::LogEntry;
+use super::hint::Hint;
+use super::legacy;
+use super::options::{CompactionThreshold, KvStoreOptions};
+use super::segment::{
+    encode_record, initialize_segment, list_segments, read_record, scan_segment, segment_path,
+    segment_version, sync_dir, write_header, LogPointer, TornTail, FORMAT_VERSION,
+    SEGMENT_HEADER_BYTES,
+};
+use super::syncer::Syncer;
+use crate::{Durability, KvsEngine, KvsError, Result};
+use crossbeam_skiplist::SkipMap;
+use crossbeam_utils::atomic::AtomicCell;
+use std::cell::{Cell, RefCell};
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use std::{collections, env, fs, io, path};
 
-/// The file name of the primary log file
-const LOGFILE: &str = "kvs.log";
 /// The file name of the temporary log file while compacting
 const COMPACTFILE: &str = "compact.log";
-/// The size of the log file needed before compaction occurs
-const COMPACT_BYTES: u64 = 1024 * 1024;
 
 /// Stores key-value relationships
+///
+/// Commands are appended as checksummed records to a log that is split into numbered segment
+/// files (`1.log`, `2.log`, ...). Only the highest numbered segment is written to; once it reaches
+/// the configured segment size it is sealed and a new one is started.
+///
+/// Compaction runs on a background thread. It seals the active segment, rewrites the live contents
+/// of every sealed segment into a single new segment, swaps the index over to it and removes the
+/// old segments, all while writes carry on in a fresh active segment. It also leaves behind a hint
+/// file with the index of the compacted segment, so that opening the store only has to replay the
+/// segments written after it.
+///
+/// A `KvStore` is a handle that can be cloned and handed to other threads; every clone refers to
+/// the same store. Reads never wait on writers: the index is a concurrent skip list whose entries
+/// are updated in place, and each handle keeps its own segment readers and reads records by
+/// position. Only writers are serialized, on the lock for the active segment. Writes from
+/// concurrent handles are committed in batches, with one append and (depending on the
+/// `Durability`) one flush to disk for the whole batch.
 pub struct KvStore {
+    shared: Arc<Shared>,
+    /// Segment files this handle has opened for reading, which are never shared with other handles
+    readers: RefCell<collections::HashMap<u64, fs::File>>,
+    generation: Cell<u64>,
+    /// The compaction thread, which a read-only store does not have
+    compactor: Option<Arc<Compactor>>,
+    /// The thread flushing writes to disk, which only `Durability::GroupCommit` needs. It is only
+    /// held so that it stops along with the last handle to the store.
+    _syncer: Option<Arc<Syncer>>,
+}
+
+/// The state of a `KvStore` that is shared with its compaction thread
+pub struct Shared {
     root: path::PathBuf,
-    log: fs::File,
+    options: KvStoreOptions,
+    /// The writer for the active segment, which a read-only store does not have
+    writer: Option<Mutex<Writer>>,
+    /// Where the newest record for every key is. Reads never lock it; writers (and compaction)
+    /// only change it while holding `writer`. Overwrites swap the pointer of an existing entry
+    /// rather than inserting a new one, so a key that stays set never goes missing for readers.
+    index: SkipMap<String, AtomicCell<LogPointer>>,
+    /// Changes waiting to be committed by the next writer to lock `writer`
+    queue: CommitQueue,
+    /// Incremented every time a compaction removes segments, so that open readers can be dropped
+    generation: AtomicU64,
+}
+
+/// The active segment and the bookkeeping needed to append to it
+struct Writer {
+    active: u64,
+    file: fs::File,
+    active_size: u64,
+    /// Whether the active segment holds writes that have not been flushed to disk yet
+    dirty: bool,
+    /// The combined size of every record in every segment
     size: u64,
-    entries: collections::HashMap<String, u64>,
+    /// The combined size of the records the index points at. Everything else in `size` belongs to
+    /// records that were overwritten or removed (or to removals themselves), and is only
+    /// reclaimed by compaction.
+    live: u64,
+    /// Whether a failed batch left records behind that could not be removed again, in which case
+    /// nothing more may be written
+    poisoned: bool,
 }
 
 impl KvStore {
@@ -30,19 +98,30 @@ impl KvStore {
         KvStore::open(cwd.as_path())
     }
 
-    /// Creates a new `KvStore` in a given working directory. All logs, indexes, etc will be
-    /// read from or created here.
+    /// Creates a new `KvStore` in a given working directory, using the default `KvStoreOptions`.
+    /// All logs, indexes, etc will be read from or created here.
     ///
     /// # Arguments
     ///
-    /// `path` - the
+    /// `path` - the directory holding the log segments
     ///
     /// # Errors
     ///
     /// - A `KvsError::BadPathError` will occur if `path` does not exist or is not a directory
+    /// - A `KvsError::BadOptionError` will occur if the options ask for a zero group commit
+    ///   interval, or for a stale ratio that is not above 0 and at most 1
+    /// - A `KvsError::StoreMissingError` will occur if `path` holds no store and the options do
+    ///   not allow creating one
+    /// - A `KvsError::StoreExistsError` will occur if `path` holds a store and the options require
+    ///   that it does not
+    /// - A `KvsError::ReadOnlyError` will occur if the store is opened read-only but its log was
+    ///   written in an older format and has to be upgraded first
     /// - A `KvsError::EngineMismatchError` will occur if `path` is not compatable with this engine
+    /// - A `KvsError::CorruptionError` will occur if a record in the log is damaged (an
+    ///   interrupted write at the very end of the log is discarded instead)
+    /// - A `KvsError::FormatVersionError` will occur if a segment uses an unknown format version
     /// - A `KvsError::IoError` will occur if file operations fail
-    /// - A `KvsError::SerdeError` will occur if reading from the logfile fails
+    /// - A `KvsError::BincodeError` will occur if reading from the logfile fails
     ///
     /// # Example
     ///
@@ -50,47 +129,502 @@ impl KvStore {
     /// let mut kvs = kvs::KvStore::open(std::path::Path::new("/var/db/"));
     /// ```
     pub fn open(path: &path::Path) -> Result<KvStore> {
+        KvStoreOptions::new().open(path)
+    }
+
+    /// Opens the `KvStore` in `path` as described by `options`
+    pub(super) fn open_with(path: &path::Path, options: &KvStoreOptions) -> Result<KvStore> {
         let path_str = path.to_str().unwrap().to_owned();
         if !path.is_dir() {
             return Err(KvsError::BadPathError(path_str));
         }
+        options.durability.check()?;
+        options.compaction_threshold.check()?;
 
         let root = path.to_path_buf();
-        let (mut log, size) = initialize_logfile(&root)?;
-        let entries = initialize_entries(&mut log);
-        Ok(KvStore {
+        let exists = legacy::log_exists(&root) || !list_segments(&root)?.is_empty();
+        if !exists && !options.create_if_missing {
+            return Err(KvsError::StoreMissingError(path_str));
+        }
+        if exists && options.error_if_exists {
+            return Err(KvsError::StoreExistsError(path_str));
+        }
+        if options.read_only {
+            if legacy::log_exists(&root) {
+                return Err(KvsError::ReadOnlyError);
+            }
+        } else {
+            discard_compactfile(&root)?;
+            upgrade_legacy_log(&root)?;
+        }
+
+        let mut segments = list_segments(&root)?;
+        let mut entries = collections::HashMap::new();
+        let mut size = 0;
+        let mut compacted = None;
+        if let Some(hint) = Hint::load(&root)? {
+            // segments before the hinted one are leftovers of a compaction that was interrupted
+            // while removing them, and everything in them is already part of the hinted segment
+            if !options.read_only {
+                for &segment in segments.iter().filter(|&&segment| segment < hint.segment) {
+                    fs::remove_file(segment_path(&root, segment))?;
+                }
+            }
+            segments.retain(|&segment| segment > hint.segment);
+            entries = hint.entries;
+            size = hint.size - SEGMENT_HEADER_BYTES;
+            compacted = Some(hint.segment);
+        }
+        for (i, &segment) in segments.iter().enumerate() {
+            let tail = match (i + 1 == segments.len(), options.read_only) {
+                (false, _) => TornTail::Reject,
+                (true, false) => TornTail::Truncate,
+                (true, true) => TornTail::Ignore,
+            };
+            if options.read_only {
+                let version = segment_version(&root, segment)?;
+                if matches!(version, Some(version) if version < FORMAT_VERSION) {
+                    return Err(KvsError::ReadOnlyError);
+                }
+            } else {
+                upgrade_segment(&root, segment, tail)?;
+            }
+            let segment_size = initialize_entries(&root, segment, tail, &mut entries)?;
+            size += segment_size.saturating_sub(SEGMENT_HEADER_BYTES);
+        }
+        let live = entries.values().map(|pointer| pointer.length).sum();
+
+        let writer = if options.read_only {
+            None
+        } else {
+            // the hinted segment is never written to again, as that would make the hint stale
+            let active = match segments.last() {
+                Some(&segment) => segment,
+                None => compacted.map_or(1, |segment| segment + 1),
+            };
+            let (file, active_size) = initialize_segment(&root, active)?;
+            if options.durability != Durability::None {
+                sync_dir(&root)?;
+            }
+            Some(Mutex::new(Writer {
+                active,
+                file,
+                active_size,
+                dirty: true,
+                size,
+                live,
+                poisoned: false,
+            }))
+        };
+        let shared = Arc::new(Shared {
             root,
-            log,
-            size,
-            entries,
+            options: options.clone(),
+            writer,
+            index: entries
+                .into_iter()
+                .map(|(key, pointer)| (key, AtomicCell::new(pointer)))
+                .collect(),
+            queue: CommitQueue::default(),
+            generation: AtomicU64::new(0),
+        });
+        let compactor = if options.read_only {
+            None
+        } else {
+            Some(Arc::new(Compactor::spawn(shared.clone())?))
+        };
+        let syncer = match options.durability {
+            Durability::GroupCommit(interval) if !options.read_only => {
+                Some(Arc::new(Syncer::spawn(shared.clone(), interval)?))
+            }
+            _ => None,
+        };
+        Ok(KvStore {
+            shared,
+            readers: RefCell::new(collections::HashMap::new()),
+            generation: Cell::new(0),
+            compactor,
+            _syncer: syncer,
         })
     }
 
-    fn compact(&mut self) -> Result<()> {
+    /// Compacts the log on the background compaction thread and waits for it to finish. Writes
+    /// from other handles carry on while it runs.
+    ///
+    /// # Errors
+    ///
+    /// - A `KvsError::ReadOnlyError` will occur if the store was opened read-only
+    /// - A `KvsError::IoError` will occur if file operations fail
+    /// - A `KvsError::CorruptionError` will occur if a record being copied is damaged
+    pub fn compact(&self) -> Result<()> {
+        match &self.compactor {
+            Some(compactor) => compactor.compact(),
+            None => Err(KvsError::ReadOnlyError),
+        }
+    }
+
+    /// Reports the state of background compaction
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// if let Ok(kvs) = kvs::KvStore::new() {
+    ///     let progress = kvs.compaction_progress();
+    ///     println!("{} of {} entries copied", progress.entries_copied, progress.entries_total);
+    /// }
+    /// ```
+    pub fn compaction_progress(&self) -> CompactionProgress {
+        self.compactor
+            .as_ref()
+            .map_or_else(CompactionProgress::default, |compactor| {
+                compactor.progress()
+            })
+    }
+
+    /// Drops every open segment reader once a compaction has removed segments
+    fn refresh_readers(&self) {
+        let generation = self.shared.generation.load(Ordering::SeqCst);
+        if generation != self.generation.get() {
+            self.readers.borrow_mut().clear();
+            self.generation.set(generation);
+        }
+    }
+}
+
+impl Clone for KvStore {
+    /// Creates another handle to the same store, with segment readers of its own
+    fn clone(&self) -> KvStore {
+        KvStore {
+            shared: self.shared.clone(),
+            readers: RefCell::new(collections::HashMap::new()),
+            generation: self.generation.clone(),
+            compactor: self.compactor.clone(),
+            _syncer: self._syncer.clone(),
+        }
+    }
+}
+
+impl Shared {
+    /// Compacts every segment up to and including the active one, which is sealed first so that
+    /// writes can carry on in a new segment while the compaction runs
+    pub fn compact(
+        &self,
+        readers: &mut collections::HashMap<u64, fs::File>,
+        progress: &ProgressCounters,
+    ) -> Result<()> {
+        // the compacted segment sits between every sealed segment and the next active one, so a
+        // replay of the directory in segment order always sees the newest command for each key last
+        let writer_lock = self.writer.as_ref().ok_or(KvsError::ReadOnlyError)?;
+        let (sealed, sealed_size) = {
+            let mut writer = writer_lock.lock().unwrap();
+            let sealed = writer.active;
+            // the new active segment holds no records yet, so everything in `size` is sealed
+            let sealed_size = writer.size;
+            writer.roll(&self.root, &self.options, sealed + 2)?;
+            (sealed, sealed_size)
+        };
+        let compacted = sealed + 1;
+
+        let mut live: Vec<(String, LogPointer)> = self
+            .index
+            .iter()
+            .filter(|entry| entry.value().load().segment <= sealed)
+            .map(|entry| (entry.key().clone(), entry.value().load()))
+            .collect();
+        live.sort_by_key(|(_, pointer)| (pointer.segment, pointer.offset));
+        progress.start(live.len() as u64);
+
         let mut compactfile = initialize_compactfile(&self.root)?;
         let mut writer = io::BufWriter::new(&mut compactfile);
-        let mut offset: usize = 0;
-
-        for (_, pos) in self.entries.iter_mut() {
-            self.log.seek(io::SeekFrom::Start(*pos))?;
-            let mut reader = io::BufReader::new(&mut self.log);
-            let mut line = String::new();
-            reader.read_line(&mut line)?;
-            *pos = offset as u64;
-            offset += line.len();
-            writer.write_all(&(line.into_bytes()))?;
+        let mut offset = SEGMENT_HEADER_BYTES;
+        let mut moved = collections::HashMap::with_capacity(live.len());
+        for (key, pointer) in &live {
+            let payload = read_record(&self.root, readers, *pointer)?;
+            writer.write_all(&encode_record(&payload))?;
+            let compacted_pointer = LogPointer {
+                segment: compacted,
+                offset,
+                length: pointer.length,
+            };
+            moved.insert(key.clone(), compacted_pointer);
+            offset += pointer.length;
+            progress.copied();
         }
+        writer.flush()?;
         drop(writer);
+        compactfile.sync_all()?;
         drop(compactfile);
-        publish_compactfile(&self.root)?;
+        publish_compactfile(&self.root, compacted)?;
+
+        // only keys that were not written or removed in the meantime move to the compacted segment
+        {
+            let mut writer = writer_lock.lock().unwrap();
+            for (key, pointer) in &live {
+                if let Some(entry) = self.index.get(key) {
+                    if entry.value().load() == *pointer {
+                        entry.value().store(moved[key]);
+                    }
+                }
+            }
+            writer.size = writer.size - sealed_size + offset - SEGMENT_HEADER_BYTES;
+        }
+        let hint = Hint {
+            segment: compacted,
+            size: offset,
+            entries: moved,
+        };
+        hint.store(&self.root)?;
+
+        // a crash from here on leaves some of the old segments behind, but since they all come
+        // before the compacted segment a replay still ends up with the same entries
+        readers.clear();
+        for segment in list_segments(&self.root)? {
+            if segment < compacted {
+                fs::remove_file(segment_path(&self.root, segment))?;
+            }
+        }
+        sync_dir(&self.root)?;
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Flushes every write made so far to disk
+    pub fn sync(&self) -> Result<()> {
+        match &self.writer {
+            Some(writer) => writer.lock().unwrap().sync(),
+            None => Ok(()),
+        }
+    }
+
+    /// Appends `entry` to the log as part of the next batch and waits until that batch is
+    /// committed. Returns whether the log has grown enough to need compacting.
+    fn commit(&self, entry: LogEntry) -> Result<bool> {
+        let writer_lock = self.writer.as_ref().ok_or(KvsError::ReadOnlyError)?;
+        let payload = entry.encode()?;
+        let ticket = self.queue.enqueue(entry, payload);
+
+        let mut writer = writer_lock.lock().unwrap();
+        // whoever held the lock before may have committed this change along with their own
+        if let Some(result) = self.queue.take(ticket) {
+            return result.map(|_| false);
+        }
+        let batch = self.queue.drain();
+        let results = self.commit_batch(&mut writer, &batch);
+        self.queue.complete(results);
+        match self.queue.take(ticket) {
+            Some(result) => result.map(|_| writer.needs_compaction(&self.options)),
+            None => Err(KvsError::UnknownError),
+        }
+    }
+
+    /// Appends a batch of changes to the log in one go and then updates the index to match,
+    /// returning the outcome of each change. Removals of keys that do not exist (taking earlier
+    /// changes in the batch into account) fail on their own without failing the batch.
+    fn commit_batch(&self, writer: &mut Writer, batch: &[PendingWrite]) -> Vec<(u64, Result<()>)> {
+        let mut results = Vec::with_capacity(batch.len());
+        let mut accepted = Vec::with_capacity(batch.len());
+        let mut present = collections::HashMap::new();
+        for write in batch {
+            match &write.entry {
+                LogEntry::Set { key, .. } => {
+                    present.insert(key, true);
+                    accepted.push(write);
+                }
+                LogEntry::Remove { key } => {
+                    let exists = present
+                        .get(key)
+                        .cloned()
+                        .unwrap_or_else(|| self.index.contains_key(key));
+                    if exists {
+                        present.insert(key, false);
+                        accepted.push(write);
+                    } else {
+                        results.push((write.ticket, Err(KvsError::BadRemovalError)));
+                    }
+                }
+            }
+        }
 
-        let (log, size) = initialize_logfile(&self.root)?;
-        self.log = log;
+        let payloads: Vec<&[u8]> = accepted.iter().map(|write| &write.payload[..]).collect();
+        let pointers = match writer.append(&self.root, &self.options, &payloads) {
+            Ok(pointers) => pointers,
+            Err(err) => {
+                results.extend(
+                    accepted
+                        .iter()
+                        .map(|write| (write.ticket, Err(batch_error(&err)))),
+                );
+                return results;
+            }
+        };
+        for (write, pointer) in accepted.iter().zip(pointers) {
+            match &write.entry {
+                LogEntry::Set { key, .. } => {
+                    writer.live += pointer.length;
+                    match self.index.get(key) {
+                        Some(entry) => writer.live -= entry.value().swap(pointer).length,
+                        None => {
+                            self.index.insert(key.clone(), AtomicCell::new(pointer));
+                        }
+                    }
+                }
+                LogEntry::Remove { key } => {
+                    if let Some(old) = self.index.remove(key) {
+                        writer.live -= old.value().load().length;
+                    }
+                }
+            }
+            results.push((write.ticket, Ok(())));
+        }
+        results
+    }
+}
+
+impl Writer {
+    /// Appends serialized entries to the active segment with a single write (and, for
+    /// `Durability::EveryWrite`, a single flush), starting a new segment whenever an entry would
+    /// push the active one past the configured segment size. If any part of the batch fails, all
+    /// of it is undone, so that none of it turns up again when the store is opened.
+    fn append(
+        &mut self,
+        root: &path::Path,
+        options: &KvStoreOptions,
+        payloads: &[&[u8]],
+    ) -> Result<Vec<LogPointer>> {
+        if self.poisoned {
+            return Err(KvsError::InternalError(
+                "An earlier write to the log failed and could not be undone".to_owned(),
+            ));
+        }
+        let (segment, active_size, size) = (self.active, self.active_size, self.size);
+        let result = self.append_records(root, options, payloads);
+        if result.is_err() {
+            if let Err(rewind_err) = self.rewind(root, options, segment, active_size, size) {
+                error!("Undoing a failed write to the log failed: {}", rewind_err);
+                self.poisoned = true;
+            }
+        }
+        result
+    }
+
+    fn append_records(
+        &mut self,
+        root: &path::Path,
+        options: &KvStoreOptions,
+        payloads: &[&[u8]],
+    ) -> Result<Vec<LogPointer>> {
+        let mut pointers = Vec::with_capacity(payloads.len());
+        let mut buffer = Vec::new();
+        for payload in payloads {
+            let record = encode_record(payload);
+            let length = record.len() as u64;
+            let offset = self.active_size + buffer.len() as u64;
+            if offset > SEGMENT_HEADER_BYTES && offset + length > options.segment_bytes {
+                self.write(&buffer)?;
+                buffer.clear();
+                self.roll(root, options, self.active + 1)?;
+            }
+
+            pointers.push(LogPointer {
+                segment: self.active,
+                offset: self.active_size + buffer.len() as u64,
+                length,
+            });
+            buffer.extend_from_slice(&record);
+        }
+        self.write(&buffer)?;
+        if options.durability == Durability::EveryWrite {
+            self.sync()?;
+        }
+        Ok(pointers)
+    }
+
+    /// Writes already framed records to the end of the active segment
+    fn write(&mut self, buffer: &[u8]) -> Result<()> {
+        if !buffer.is_empty() {
+            // even a failed write may have left part of the buffer in the segment
+            self.dirty = true;
+            self.file.write_all(buffer)?;
+            self.active_size += buffer.len() as u64;
+            self.size += buffer.len() as u64;
+        }
+        Ok(())
+    }
+
+    /// Moves writes back to the point where `segment` was the active segment and held
+    /// `active_size` bytes, removing every segment started since and truncating away whatever was
+    /// written to `segment` after that point
+    fn rewind(
+        &mut self,
+        root: &path::Path,
+        options: &KvStoreOptions,
+        segment: u64,
+        active_size: u64,
+        size: u64,
+    ) -> io::Result<()> {
+        if self.active != segment {
+            self.file = fs::OpenOptions::new()
+                .append(true)
+                .open(segment_path(root, segment))?;
+            for later in segment + 1..=self.active {
+                fs::remove_file(segment_path(root, later))?;
+            }
+            self.active = segment;
+            if options.durability != Durability::None {
+                sync_dir(root)?;
+            }
+        }
+        self.file.set_len(active_size)?;
+        self.active_size = active_size;
         self.size = size;
+        self.dirty = true;
+        Ok(())
+    }
+
+    /// Whether the log has grown past the configured compaction threshold
+    fn needs_compaction(&self, options: &KvStoreOptions) -> bool {
+        let uncompacted = self.size - self.live;
+        match options.compaction_threshold {
+            CompactionThreshold::Bytes(bytes) => uncompacted > bytes,
+            CompactionThreshold::StaleRatio(ratio) => {
+                self.size > options.segment_bytes && uncompacted as f64 >= ratio * self.size as f64
+            }
+        }
+    }
+
+    /// Seals the active segment and moves writes to `segment`. The sealed segment is flushed
+    /// first whatever the durability, since the sync thread only ever looks at the active one and
+    /// only the last segment may have a torn tail when the store is opened again.
+    fn roll(&mut self, root: &path::Path, options: &KvStoreOptions, segment: u64) -> Result<()> {
+        self.sync()?;
+        let (file, active_size) = initialize_segment(root, segment)?;
+        if options.durability != Durability::None {
+            sync_dir(root)?;
+        }
+        self.active = segment;
+        self.file = file;
+        self.active_size = active_size;
+        self.dirty = true;
+        Ok(())
+    }
+
+    /// Flushes the active segment to disk if it has changed since it was last flushed
+    fn sync(&mut self) -> Result<()> {
+        if self.dirty {
+            self.file.sync_data()?;
+            self.dirty = false;
+        }
         Ok(())
     }
 }
 
+impl Drop for Writer {
+    fn drop(&mut self) {
+        if let Err(err) = self.sync() {
+            error!("Flushing the log to disk failed: {}", err);
+        }
+    }
+}
+
 impl KvsEngine for KvStore {
     /// Retrieves the value for a given key (if that key is valid)
     ///
@@ -102,32 +636,48 @@ impl KvsEngine for KvStore {
     ///
     /// - A `KvsError::UnknownError` will occur for all internal errors
     /// - A `KvsError::IoError` will occur if file operations fail
-    /// - A `KvsError::SerdeError` will occur if reading from the logfile fails
+    /// - A `KvsError::CorruptionError` will occur if the record for `key` is damaged
+    /// - A `KvsError::BincodeError` will occur if reading from the logfile fails
     ///
     /// # Example
     ///
     ///```
     /// use kvs::KvsEngine;
     /// match kvs::KvStore::new() {
-    ///     Ok(mut kvs) => { kvs.get(String::from("key")); }
+    ///     Ok(kvs) => { kvs.get(String::from("key")); }
     ///     Err(_) => {}
     /// }
     ///```
-    fn get(&mut self, key: String) -> Result<Option<String>> {
-        match self.entries.get(&key) {
-            Some(offset) => {
-                self.log.seek(io::SeekFrom::Start(*offset))?;
-                let mut reader = io::BufReader::new(&mut self.log);
-                let mut line = String::new();
-                reader.read_line(&mut line)?;
-
-                match from_str(&line) {
-                    Ok(KvsRequest::Set { value, .. }) => Ok(Some(value)),
-                    Err(err) => Err(KvsError::SerdeError(err)),
-                    _ => Err(KvsError::UnknownError),
+    fn get(&self, key: String) -> Result<Option<String>> {
+        self.refresh_readers();
+        let mut readers = self.readers.borrow_mut();
+        let mut pointer = match self.shared.index.get(&key) {
+            Some(entry) => entry.value().load(),
+            None => return Ok(None),
+        };
+        loop {
+            let err = match read_record(&self.shared.root, &mut readers, pointer) {
+                Ok(payload) => {
+                    return match LogEntry::decode(&payload)? {
+                        LogEntry::Set { value, .. } => Ok(Some(value)),
+                        LogEntry::Remove { .. } => Err(KvsError::UnknownError),
+                    };
                 }
+                Err(KvsError::IoError(err)) if err.kind() == io::ErrorKind::NotFound => err,
+                Err(err) => return Err(err),
+            };
+            // a compaction may have removed the segment after the index was read, in which case
+            // the key has moved somewhere else by now
+            match self
+                .shared
+                .index
+                .get(&key)
+                .map(|entry| entry.value().load())
+            {
+                Some(moved) if moved != pointer => pointer = moved,
+                Some(_) => return Err(KvsError::IoError(err)),
+                None => return Ok(None),
             }
-            None => Ok(None),
         }
     }
 
@@ -141,29 +691,21 @@ impl KvsEngine for KvStore {
     /// # Errors
     ///
     /// - A `KvsError::IoError` will occur if file operations fail
-    /// - A `KvsError::SerdeError` will occur if seralizing content for the logfile fails
+    /// - A `KvsError::BincodeError` will occur if seralizing content for the logfile fails
     ///
     /// # Example
     ///
     ///```
     /// use kvs::KvsEngine;
     /// match kvs::KvStore::new() {
-    ///     Ok(mut kvs) => { kvs.set(String::from("key"), String::from("value")); }
+    ///     Ok(kvs) => { kvs.set(String::from("key"), String::from("value")); }
     ///     Err(_) => {}
     /// }
     ///```
-    fn set(&mut self, key: String, value: String) -> Result<()> {
-        let cmd = KvsRequest::Set {
-            key: key.to_owned(),
-            value,
-        };
-        let serialized = format!("{}\n", to_string(&cmd)?).into_bytes();
-        let offset = self.log.seek(io::SeekFrom::End(0))?;
-        self.log.write_all(&serialized)?;
-        self.entries.insert(key, offset);
-        self.size += serialized.len() as u64;
-        if self.size > COMPACT_BYTES {
-            self.compact()?;
+    fn set(&self, key: String, value: String) -> Result<()> {
+        let needs_compaction = self.shared.commit(LogEntry::Set { key, value })?;
+        if let (true, Some(compactor)) = (needs_compaction, &self.compactor) {
+            compactor.trigger();
         }
         Ok(())
     }
@@ -178,84 +720,142 @@ impl KvsEngine for KvStore {
     ///
     /// - A `KvsError::BadRemovalError` will occur if the requested key was not found
     /// - A `KvsError::IoError` will occur if file operations fail
-    /// - A `KvsError::SerdeError` will occur if seralizing content for the logfile fails
+    /// - A `KvsError::BincodeError` will occur if seralizing content for the logfile fails
     ///
     /// # Example
     ///
     /// ```
     /// use kvs::KvsEngine;
     /// match kvs::KvStore::new() {
-    ///     Ok(mut kvs) => { kvs.remove(String::from("key")); }
+    ///     Ok(kvs) => { kvs.remove(String::from("key")); }
     ///     Err(_) => {}
     /// }
     /// ```
-    fn remove(&mut self, key: String) -> Result<()> {
-        match self.entries.get(&key) {
-            Some(_) => {
-                let cmd = KvsRequest::Remove {
-                    key: key.to_owned(),
-                };
-                let serialized = format!("{}\n", to_string(&cmd)?).into_bytes();
-                self.log.seek(io::SeekFrom::End(0))?;
-                self.log.write_all(&serialized)?;
-                self.entries.remove(&key);
-                self.size += serialized.len() as u64;
-                if self.size > COMPACT_BYTES {
-                    self.compact()?;
-                }
-                Ok(())
-            }
-            None => Err(KvsError::BadRemovalError),
+    fn remove(&self, key: String) -> Result<()> {
+        let needs_compaction = self.shared.commit(LogEntry::Remove { key })?;
+        if let (true, Some(compactor)) = (needs_compaction, &self.compactor) {
+            compactor.trigger();
         }
+        Ok(())
     }
-}
 
-fn initialize_logfile(root: &path::PathBuf) -> std::result::Result<(fs::File, u64), io::Error> {
-    let log_path = root.join(LOGFILE);
-    let log = fs::OpenOptions::new()
-        .create(true)
-        .read(true)
-        .write(true)
-        .append(true)
-        .open(&log_path)?;
-    let log_size = fs::metadata(log_path)?.len();
-    Ok((log, log_size))
+    /// Flushes the active segment to disk. A compaction that is running carries on in the
+    /// background; dropping the last handle to the store waits for it to finish.
+    ///
+    /// # Errors
+    ///
+    /// - A `KvsError::IoError` will occur if file operations fail
+    fn flush(&self) -> Result<()> {
+        self.shared.sync()
+    }
 }
 
-fn initialize_compactfile(root: &path::PathBuf) -> std::result::Result<fs::File, io::Error> {
+fn initialize_compactfile(root: &path::Path) -> std::result::Result<fs::File, io::Error> {
     let compact_path = root.join(COMPACTFILE);
-    fs::OpenOptions::new()
+    let mut compactfile = fs::OpenOptions::new()
         .create(true)
         .truncate(true)
         .read(true)
         .write(true)
-        .open(compact_path)
+        .open(compact_path)?;
+    write_header(&mut compactfile)?;
+    Ok(compactfile)
 }
 
-fn publish_compactfile(root: &path::PathBuf) -> std::result::Result<(), io::Error> {
-    let log_path = root.join(LOGFILE);
+/// Moves a finished compaction file into place as `segment`. The rename is atomic, so after a
+/// crash the directory holds either the complete compacted segment or none of it.
+fn publish_compactfile(root: &path::Path, segment: u64) -> std::result::Result<(), io::Error> {
     let compact_path = root.join(COMPACTFILE);
-    fs::copy(&compact_path, log_path)?;
-    fs::remove_file(compact_path)?;
+    fs::rename(&compact_path, segment_path(root, segment))?;
+    sync_dir(root)
+}
+
+/// Removes a compaction file left behind by a compaction that never reached
+/// `publish_compactfile`. The segments it was built from are still intact at this point.
+fn discard_compactfile(root: &path::Path) -> std::result::Result<(), io::Error> {
+    let compact_path = root.join(COMPACTFILE);
+    if compact_path.exists() {
+        warn!("Discarding unfinished compaction file {:?}", compact_path);
+        fs::remove_file(compact_path)?;
+        sync_dir(root)?;
+    }
+    Ok(())
+}
+
+/// Writes `entries` into a fresh segment file and publishes it as `segment`, atomically replacing
+/// any segment that already has that id
+fn publish_entries(root: &path::Path, segment: u64, entries: &[LogEntry]) -> Result<()> {
+    let mut compactfile = initialize_compactfile(root)?;
+    let mut writer = io::BufWriter::new(&mut compactfile);
+    for entry in entries {
+        writer.write_all(&encode_record(&entry.encode()?))?;
+    }
+    writer.flush()?;
+    drop(writer);
+    compactfile.sync_all()?;
+    drop(compactfile);
+    publish_compactfile(root, segment)?;
     Ok(())
 }
 
-fn initialize_entries(log: &mut fs::File) -> collections::HashMap<String, u64> {
-    let mut entries = collections::HashMap::new();
-    let reader = io::BufReader::new(log);
-    let mut offset: usize = 0;
-    for line in reader.lines() {
-        let line = line.unwrap();
-        match from_str(&line) {
-            Ok(KvsRequest::Set { key, .. }) => {
-                entries.insert(key, offset as u64);
+/// Converts a newline-delimited JSON `kvs.log` from before the log was segmented into a binary
+/// segment placed after any existing ones. The new segment is published the same way a compacted
+/// one is, so an interrupted upgrade is simply redone on the next open.
+fn upgrade_legacy_log(root: &path::Path) -> Result<()> {
+    let entries = match legacy::read_log(root)? {
+        Some(entries) => entries,
+        None => return Ok(()),
+    };
+    let segment = list_segments(root)?.last().map_or(1, |last| last + 1);
+    info!(
+        "Upgrading legacy log with {} entries into segment {}",
+        entries.len(),
+        segment
+    );
+    publish_entries(root, segment, &entries)?;
+    legacy::remove_log(root)
+}
+
+/// Rewrites a segment written in an older format version in the current one. The old segment is
+/// only replaced once the new one is complete, so an interrupted upgrade is redone on the next
+/// open.
+fn upgrade_segment(root: &path::Path, segment: u64, tail: TornTail) -> Result<()> {
+    let version = match segment_version(root, segment)? {
+        Some(version) if version < FORMAT_VERSION => version,
+        _ => return Ok(()),
+    };
+    info!(
+        "Upgrading segment {} from format version {} to {}",
+        segment, version, FORMAT_VERSION
+    );
+
+    // version 1 is the only older format so far
+    let mut entries = Vec::new();
+    scan_segment(root, segment, tail, |payload, _| {
+        if let Some(entry) = legacy::decode_v1(payload)? {
+            entries.push(entry);
+        }
+        Ok(())
+    })?;
+    publish_entries(root, segment, &entries)
+}
+
+/// Replays a single segment into `entries`, returning the size of the segment
+fn initialize_entries(
+    root: &path::Path,
+    segment: u64,
+    tail: TornTail,
+    entries: &mut collections::HashMap<String, LogPointer>,
+) -> Result<u64> {
+    scan_segment(root, segment, tail, |payload, pointer| {
+        match LogEntry::decode(payload)? {
+            LogEntry::Set { key, .. } => {
+                entries.insert(key, pointer);
             }
-            Ok(KvsRequest::Remove { key, .. }) => {
+            LogEntry::Remove { key } => {
                 entries.remove(&key);
             }
-            _ => {}
         };
-        offset += line.len() + 1;
-    }
-    entries
+        Ok(())
+    })
 }
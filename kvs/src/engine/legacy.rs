@@ -0,0 +1,98 @@
+use super::entry::LogEntry;
+use super::segment::sync_dir;
+use crate::Result;
+use serde::Deserialize;
+use serde_json::from_slice;
+use std::io::BufRead;
+use std::{collections, fs, io, path};
+
+/// The file name of the single newline-delimited JSON log used before segments were introduced
+const LEGACY_LOGFILE: &str = "kvs.log";
+
+/// A frozen copy of `KvsRequest` as it was when the log stored network requests directly. Both
+/// the legacy JSON log and version 1 segments (as bincode) were written with this layout, so it
+/// must never change.
+#[derive(Deserialize)]
+enum LegacyCommand {
+    Get {
+        #[allow(dead_code)]
+        key: String,
+    },
+    Set {
+        key: String,
+        value: String,
+    },
+    Remove {
+        key: String,
+    },
+}
+
+impl LegacyCommand {
+    fn into_entry(self) -> Option<LogEntry> {
+        match self {
+            LegacyCommand::Get { .. } => None,
+            LegacyCommand::Set { key, value } => Some(LogEntry::Set { key, value }),
+            LegacyCommand::Remove { key } => Some(LogEntry::Remove { key }),
+        }
+    }
+}
+
+/// Decodes a record payload written in segment format version 1. Payloads that never described
+/// a change to the store are returned as `None`.
+pub fn decode_v1(payload: &[u8]) -> Result<Option<LogEntry>> {
+    let command: LegacyCommand = bincode::deserialize(payload)?;
+    Ok(command.into_entry())
+}
+
+/// Whether `root` holds a legacy `kvs.log`
+pub fn log_exists(root: &path::Path) -> bool {
+    root.join(LEGACY_LOGFILE).is_file()
+}
+
+/// Reads the entries needed to rebuild the contents of a legacy `kvs.log`, in the order they were
+/// last written. Returns `None` if the directory has no legacy log.
+///
+/// A line that cannot be parsed is skipped, as the old format had no way to tell a torn write
+/// apart from any other damage.
+pub fn read_log(root: &path::Path) -> Result<Option<Vec<LogEntry>>> {
+    let log_path = root.join(LEGACY_LOGFILE);
+    if !log_path.is_file() {
+        return Ok(None);
+    }
+
+    let reader = io::BufReader::new(fs::File::open(&log_path)?);
+    let mut entries = collections::HashMap::new();
+    for (sequence, line) in reader.split(b'\n').enumerate() {
+        let line = line?;
+        match from_slice(&line).map(LegacyCommand::into_entry) {
+            Ok(Some(LogEntry::Set { key, value })) => {
+                entries.insert(key, (sequence, value));
+            }
+            Ok(Some(LogEntry::Remove { key })) => {
+                entries.remove(&key);
+            }
+            _ => {
+                warn!(
+                    "Skipping unreadable line {} of {:?}",
+                    sequence + 1,
+                    log_path
+                );
+            }
+        }
+    }
+
+    let mut entries: Vec<_> = entries.into_iter().collect();
+    entries.sort_by_key(|(_, (sequence, _))| *sequence);
+    let entries = entries
+        .into_iter()
+        .map(|(key, (_, value))| LogEntry::Set { key, value })
+        .collect();
+    Ok(Some(entries))
+}
+
+/// Deletes the legacy `kvs.log` once its contents have been moved into a segment
+pub fn remove_log(root: &path::Path) -> Result<()> {
+    fs::remove_file(root.join(LEGACY_LOGFILE))?;
+    sync_dir(root)?;
+    Ok(())
+}
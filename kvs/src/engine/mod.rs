@@ -1,13 +1,50 @@
-use crate::Result;
+use crate::{KvsError, Result};
+use std::time::Duration;
+
+/// How soon a change accepted by a `KvsEngine` is guaranteed to survive a crash
+///
+/// Every engine honors the same guarantees, although an engine may do better than it promises
+/// (`KvStore` hands each write to the operating system straight away, for instance, so even with
+/// `Durability::None` its writes survive the process being killed).
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Durability {
+    /// The engine does not flush changes to disk as they are made (`KvStore` only flushes each
+    /// log segment once it is full). Changes are only guaranteed to be on disk once the engine
+    /// has been dropped; a crash may lose any number of recent changes.
+    #[default]
+    None,
+    /// Every change is flushed to disk before `set` or `remove` returns, so a change that was
+    /// acknowledged survives a crash of the process or of the whole machine.
+    EveryWrite,
+    /// Changes are flushed to disk in batches, at most the given interval after they were made.
+    /// A crash loses at most the changes acknowledged during the last interval, which must not be
+    /// zero.
+    GroupCommit(Duration),
+}
+
+impl Durability {
+    /// Fails with `KvsError::BadOptionError` if the durability cannot be honored
+    pub(crate) fn check(self) -> Result<()> {
+        match self {
+            Durability::GroupCommit(interval) if interval == Duration::from_secs(0) => Err(
+                KvsError::BadOptionError("the group commit interval must not be zero".to_owned()),
+            ),
+            _ => Ok(()),
+        }
+    }
+}
 
 /// Defines a storage interface for key-value storage
-pub trait KvsEngine {
+///
+/// Engines are handles: cloning one gives another handle to the same storage, which can be moved
+/// to another thread and used concurrently with the original.
+pub trait KvsEngine: Clone + Send + 'static {
     /// Retrieves the value for a given key (if that key is valid)
     ///
     /// # Arguments
     ///
     /// `key` - the string with which a value may be associated
-    fn get(&mut self, key: String) -> Result<Option<String>>;
+    fn get(&self, key: String) -> Result<Option<String>>;
 
     /// Sets a value for a given key. If the key is already present, it is overwrriten.
     ///
@@ -20,7 +57,7 @@ pub trait KvsEngine {
     ///
     /// - A `KvError::IoError` will occur if file operations fail
     /// - A `KvError::SerdeError` will occur if seralizing content for the logfile fails
-    fn set(&mut self, key: String, value: String) -> Result<()>;
+    fn set(&self, key: String, value: String) -> Result<()>;
 
     /// Removes a key-value relationship. If the key is not present, nothing happens.
     ///
@@ -33,11 +70,32 @@ pub trait KvsEngine {
     /// - A `KvError::BadRemovalError` will occur if the requested key was not found
     /// - A `KvError::IoError` will occur if file operations fail
     /// - A `KvError::SerdeError` will occur if seralizing content for the logfile fails
-    fn remove(&mut self, key: String) -> Result<()>;
+    fn remove(&self, key: String) -> Result<()>;
+
+    /// Flushes every change accepted so far to disk, whatever the engine's `Durability`
+    ///
+    /// # Errors
+    ///
+    /// - A `KvError::IoError` will occur if file operations fail
+    fn flush(&self) -> Result<()>;
 }
 
+#[cfg(feature = "async")]
+pub use asynchronous::AsyncKvsEngine;
 pub use self::sled::SledKvsEngine;
+pub use compactor::CompactionProgress;
 pub use kv::KvStore;
+pub use options::{CompactionThreshold, KvStoreOptions};
 
+#[cfg(feature = "async")]
+mod asynchronous;
+mod commit;
+mod compactor;
+mod entry;
+mod hint;
 mod kv;
+mod legacy;
+mod options;
+mod segment;
 mod sled;
+mod syncer;
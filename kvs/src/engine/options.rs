@@ -0,0 +1,126 @@
+use super::kv::KvStore;
+use super::Durability;
+use crate::{KvsError, Result};
+use std::path;
+
+/// The size the active segment may reach before writes move to a new segment, by default
+const DEFAULT_SEGMENT_BYTES: u64 = 256 * 1024;
+/// The amount of overwritten or removed data needed before compaction occurs, by default
+const DEFAULT_COMPACT_BYTES: u64 = 1024 * 1024;
+
+/// Decides when a `KvStore` compacts its log
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CompactionThreshold {
+    /// Compact once more than this many bytes of the log are taken up by records that have been
+    /// overwritten or removed. Live data does not count, so a large store that is mostly written
+    /// once is not rewritten over and over.
+    Bytes(u64),
+    /// Compact once at least this fraction (above 0, and at most 1) of the log is taken up by records
+    /// that have been overwritten or removed. Logs smaller than a single segment are never
+    /// compacted.
+    StaleRatio(f64),
+}
+
+impl CompactionThreshold {
+    /// Fails with `KvsError::BadOptionError` if the threshold would compact on every write or
+    /// never compact at all
+    pub(super) fn check(self) -> Result<()> {
+        match self {
+            CompactionThreshold::StaleRatio(ratio) if !(ratio > 0.0 && ratio <= 1.0) => Err(
+                KvsError::BadOptionError(format!("the stale ratio {} is not in (0, 1]", ratio)),
+            ),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Options used to open a `KvStore`, in the style of `std::fs::OpenOptions`
+///
+/// # Example
+///
+/// ```
+/// use kvs::{CompactionThreshold, KvStoreOptions};
+/// let kvs = KvStoreOptions::new()
+///     .compaction_threshold(CompactionThreshold::StaleRatio(0.5))
+///     .segment_bytes(64 * 1024)
+///     .open(std::path::Path::new("/var/db/"));
+/// ```
+#[derive(Clone, Debug)]
+pub struct KvStoreOptions {
+    pub(super) compaction_threshold: CompactionThreshold,
+    pub(super) segment_bytes: u64,
+    pub(super) durability: Durability,
+    pub(super) read_only: bool,
+    pub(super) create_if_missing: bool,
+    pub(super) error_if_exists: bool,
+}
+
+impl KvStoreOptions {
+    /// Creates the default options: compact once 1 MiB of the log is stale, 256 KiB segments,
+    /// `Durability::None`, and a writable store that is created if it does not exist yet
+    pub fn new() -> KvStoreOptions {
+        KvStoreOptions {
+            compaction_threshold: CompactionThreshold::Bytes(DEFAULT_COMPACT_BYTES),
+            segment_bytes: DEFAULT_SEGMENT_BYTES,
+            durability: Durability::default(),
+            read_only: false,
+            create_if_missing: true,
+            error_if_exists: false,
+        }
+    }
+
+    /// Sets when the log is compacted
+    pub fn compaction_threshold(&mut self, threshold: CompactionThreshold) -> &mut KvStoreOptions {
+        self.compaction_threshold = threshold;
+        self
+    }
+
+    /// Sets the size the active segment may reach before writes move to a new segment. A single
+    /// record larger than this still gets a segment of its own.
+    pub fn segment_bytes(&mut self, segment_bytes: u64) -> &mut KvStoreOptions {
+        self.segment_bytes = segment_bytes;
+        self
+    }
+
+    /// Sets when writes are flushed to disk
+    pub fn durability(&mut self, durability: Durability) -> &mut KvStoreOptions {
+        self.durability = durability;
+        self
+    }
+
+    /// Opens the store without ever writing to its directory. Writes and compactions fail with
+    /// `KvsError::ReadOnlyError`, and an interrupted write at the end of the log is skipped
+    /// rather than truncated.
+    pub fn read_only(&mut self, read_only: bool) -> &mut KvStoreOptions {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Sets whether a new store is created if the directory does not hold one yet. The directory
+    /// itself must already exist.
+    pub fn create_if_missing(&mut self, create_if_missing: bool) -> &mut KvStoreOptions {
+        self.create_if_missing = create_if_missing;
+        self
+    }
+
+    /// Sets whether opening fails if there already is a store at the path
+    pub fn error_if_exists(&mut self, error_if_exists: bool) -> &mut KvStoreOptions {
+        self.error_if_exists = error_if_exists;
+        self
+    }
+
+    /// Opens the `KvStore` in `path` with these options
+    ///
+    /// # Errors
+    ///
+    /// See `KvStore::open`
+    pub fn open(&self, path: &path::Path) -> Result<KvStore> {
+        KvStore::open_with(path, self)
+    }
+}
+
+impl Default for KvStoreOptions {
+    fn default() -> KvStoreOptions {
+        KvStoreOptions::new()
+    }
+}
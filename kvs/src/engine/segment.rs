@@ -0,0 +1,298 @@
+use crate::{KvsError, Result};
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+use std::{collections, fs, io, path};
+
+/// The extension given to every log segment file
+const SEGMENT_EXT: &str = "log";
+/// The magic number that begins every segment file
+const SEGMENT_MAGIC: [u8; 4] = *b"KVSL";
+/// The version of the on-disk format written by this build
+///
+/// - 1: records hold bincode-encoded network requests
+/// - 2: records hold bincode-encoded `LogEntry` values
+pub const FORMAT_VERSION: u32 = 2;
+/// The oldest on-disk format version that can still be read (and upgraded)
+const MIN_FORMAT_VERSION: u32 = 1;
+/// The size of the magic number and format version that begin every segment file
+pub const SEGMENT_HEADER_BYTES: u64 = 8;
+/// The size of the length and checksum fields that precede every record
+const RECORD_HEADER_BYTES: u64 = 8;
+
+/// How `scan_segment` treats a record that was cut short (or fails its checksum) at the very end
+/// of a segment
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TornTail {
+    /// The segment is not the last one in the log, so the record is corrupt
+    Reject,
+    /// The record is an interrupted write and is truncated away
+    Truncate,
+    /// The record is an interrupted write and is skipped, leaving the file untouched
+    Ignore,
+}
+
+/// The location of a single record within the segmented log
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LogPointer {
+    /// The id of the segment holding the record
+    pub segment: u64,
+    /// The position of the record (including its header) within the segment
+    pub offset: u64,
+    /// The length of the record (including its header)
+    pub length: u64,
+}
+
+/// Frames a payload as a log record: a little-endian `u32` payload length, a little-endian `u32`
+/// CRC32 of the payload, and then the payload itself
+pub fn encode_record(payload: &[u8]) -> Vec<u8> {
+    let mut record = Vec::with_capacity(RECORD_HEADER_BYTES as usize + payload.len());
+    record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    record.extend_from_slice(&checksum(payload).to_le_bytes());
+    record.extend_from_slice(payload);
+    record
+}
+
+/// The path of the file holding `segment`
+pub fn segment_path(root: &path::Path, segment: u64) -> path::PathBuf {
+    root.join(format!("{}.{}", segment, SEGMENT_EXT))
+}
+
+/// Finds the ids of every segment file in `root`, in ascending order
+pub fn list_segments(root: &path::Path) -> Result<Vec<u64>> {
+    let mut segments = Vec::new();
+    for entry in fs::read_dir(root)? {
+        let path = entry?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some(SEGMENT_EXT) {
+            continue;
+        }
+        let id = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.parse::<u64>().ok());
+        if let Some(id) = id {
+            segments.push(id);
+        }
+    }
+    segments.sort_unstable();
+    Ok(segments)
+}
+
+/// Opens a segment for appending, creating it (and writing its header) if needed, and returns it
+/// along with its size
+pub fn initialize_segment(
+    root: &path::Path,
+    segment: u64,
+) -> std::result::Result<(fs::File, u64), io::Error> {
+    let log_path = segment_path(root, segment);
+    let mut log = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&log_path)?;
+    let mut log_size = fs::metadata(log_path)?.len();
+    if log_size == 0 {
+        write_header(&mut log)?;
+        log_size = SEGMENT_HEADER_BYTES;
+    }
+    Ok((log, log_size))
+}
+
+/// Writes the magic number and format version that must begin every segment file
+pub fn write_header<W: Write>(writer: &mut W) -> std::result::Result<(), io::Error> {
+    writer.write_all(&SEGMENT_MAGIC)?;
+    writer.write_all(&FORMAT_VERSION.to_le_bytes())
+}
+
+/// Reads the format version from the header of a segment. Returns `None` if the segment is too
+/// short to hold a header.
+pub fn segment_version(root: &path::Path, segment: u64) -> Result<Option<u32>> {
+    let mut file = fs::File::open(segment_path(root, segment))?;
+    let mut file_header = [0; SEGMENT_HEADER_BYTES as usize];
+    match file.read_exact(&mut file_header) {
+        Ok(()) => Ok(Some(u32::from_le_bytes([
+            file_header[4],
+            file_header[5],
+            file_header[6],
+            file_header[7],
+        ]))),
+        Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(err) => Err(KvsError::IoError(err)),
+    }
+}
+
+/// Flushes renames, creations and removals of files in `root` to disk
+#[cfg(unix)]
+pub fn sync_dir(root: &path::Path) -> std::result::Result<(), io::Error> {
+    fs::File::open(root)?.sync_all()
+}
+
+#[cfg(not(unix))]
+pub fn sync_dir(_root: &path::Path) -> std::result::Result<(), io::Error> {
+    Ok(())
+}
+
+/// Reads the payload of the record found at `pointer`, opening its segment if it is not open
+/// already
+///
+/// # Errors
+///
+/// - A `KvsError::CorruptionError` will occur if the record does not match its checksum
+/// - A `KvsError::IoError` will occur if file operations fail
+pub fn read_record(
+    root: &path::Path,
+    readers: &mut collections::HashMap<u64, fs::File>,
+    pointer: LogPointer,
+) -> Result<Vec<u8>> {
+    let reader = match readers.entry(pointer.segment) {
+        collections::hash_map::Entry::Occupied(entry) => entry.into_mut(),
+        collections::hash_map::Entry::Vacant(entry) => {
+            entry.insert(fs::File::open(segment_path(root, pointer.segment))?)
+        }
+    };
+    let mut record = vec![0; pointer.length as usize];
+    read_exact_at(reader, &mut record, pointer.offset)?;
+    match decode_record(&record) {
+        Some(payload) if payload.len() == record.len() - RECORD_HEADER_BYTES as usize => {
+            Ok(payload.to_vec())
+        }
+        _ => Err(KvsError::CorruptionError(pointer.segment, pointer.offset)),
+    }
+}
+
+/// Reads every record of a segment in order, passing each payload and its location to `visit`.
+/// Returns the size of the valid portion of the segment.
+///
+/// Unless `tail` is `TornTail::Reject` the segment is the last one in the log, and a record cut
+/// short at the end of the file (or the final record failing its checksum) is treated as a write
+/// that was interrupted by a crash: the segment is truncated to the last complete record, or the
+/// record is merely skipped for `TornTail::Ignore`.
+///
+/// # Errors
+///
+/// - A `KvsError::CorruptionError` will occur if the segment does not start with a valid header,
+///   or if a record other than an interrupted final write is incomplete or does not match its
+///   checksum
+/// - A `KvsError::FormatVersionError` will occur if the segment was written in an unknown format
+/// - A `KvsError::IoError` will occur if file operations fail
+pub fn scan_segment<F>(root: &path::Path, segment: u64, tail: TornTail, mut visit: F) -> Result<u64>
+where
+    F: FnMut(&[u8], LogPointer) -> Result<()>,
+{
+    let path = segment_path(root, segment);
+    let size = fs::metadata(&path)?.len();
+    let mut reader = io::BufReader::new(fs::File::open(&path)?);
+    if size < SEGMENT_HEADER_BYTES {
+        if tail != TornTail::Reject {
+            // the segment was created but its header never fully made it to disk
+            if tail == TornTail::Truncate {
+                truncate_segment(&path, 0)?;
+            }
+            return Ok(0);
+        }
+        return Err(KvsError::CorruptionError(segment, 0));
+    }
+    let mut file_header = [0; SEGMENT_HEADER_BYTES as usize];
+    reader.read_exact(&mut file_header)?;
+    if file_header[..4] != SEGMENT_MAGIC {
+        return Err(KvsError::CorruptionError(segment, 0));
+    }
+    let version = u32::from_le_bytes([
+        file_header[4],
+        file_header[5],
+        file_header[6],
+        file_header[7],
+    ]);
+    if !(MIN_FORMAT_VERSION..=FORMAT_VERSION).contains(&version) {
+        return Err(KvsError::FormatVersionError(version));
+    }
+
+    let mut offset = SEGMENT_HEADER_BYTES;
+    let mut header = [0; RECORD_HEADER_BYTES as usize];
+    let mut payload = Vec::new();
+
+    while offset < size {
+        let remaining = size - offset;
+        let mut complete = false;
+        if remaining >= RECORD_HEADER_BYTES {
+            reader.read_exact(&mut header)?;
+            let length = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+            if u64::from(length) <= remaining - RECORD_HEADER_BYTES {
+                payload.resize(length as usize, 0);
+                reader.read_exact(&mut payload)?;
+                complete = true;
+            }
+        }
+
+        let length = RECORD_HEADER_BYTES + payload.len() as u64;
+        let valid = complete && {
+            let crc = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+            checksum(&payload) == crc
+        };
+        if !valid {
+            if tail != TornTail::Reject && (!complete || offset + length == size) {
+                warn!(
+                    "Discarding interrupted write at offset {} of segment {}",
+                    offset, segment
+                );
+                if tail == TornTail::Truncate {
+                    truncate_segment(&path, offset)?;
+                }
+                return Ok(offset);
+            }
+            return Err(KvsError::CorruptionError(segment, offset));
+        }
+
+        visit(
+            &payload,
+            LogPointer {
+                segment,
+                offset,
+                length,
+            },
+        )?;
+        offset += length;
+    }
+    Ok(offset)
+}
+
+/// Fills `buf` from `file` starting at `offset`, without moving the file cursor where the
+/// platform allows it
+#[cfg(unix)]
+fn read_exact_at(file: &mut fs::File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    use std::os::unix::fs::FileExt;
+    file.read_exact_at(buf, offset)
+}
+
+#[cfg(not(unix))]
+fn read_exact_at(file: &mut fs::File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    use std::io::Seek;
+    file.seek(io::SeekFrom::Start(offset))?;
+    file.read_exact(buf)
+}
+
+fn truncate_segment(path: &path::Path, size: u64) -> std::result::Result<(), io::Error> {
+    let file = fs::OpenOptions::new().write(true).open(path)?;
+    file.set_len(size)?;
+    file.sync_all()
+}
+
+/// Splits a complete record into its payload, if the checksum matches
+fn decode_record(record: &[u8]) -> Option<&[u8]> {
+    if record.len() < RECORD_HEADER_BYTES as usize {
+        return None;
+    }
+    let length = u32::from_le_bytes([record[0], record[1], record[2], record[3]]) as usize;
+    let crc = u32::from_le_bytes([record[4], record[5], record[6], record[7]]);
+    let payload = record[RECORD_HEADER_BYTES as usize..].get(..length)?;
+    if checksum(payload) == crc {
+        Some(payload)
+    } else {
+        None
+    }
+}
+
+/// Computes the CRC32 of `payload`
+pub fn checksum(payload: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(payload);
+    hasher.finalize()
+}
@@ -1,11 +1,13 @@
-use crate::{KvsError, KvsEngine, Result};
-use sled::{Db, IVec};
+use crate::{Durability, KvsEngine, KvsError, Result};
+use sled::{ConfigBuilder, Db, IVec};
 use std::{env, path, str};
 
 /// An implementation of the `sled` library that is compatible with this library's key-value store
-/// interface.
+/// interface. Clones share the same `sled` instance.
+#[derive(Clone)]
 pub struct SledKvsEngine {
     store: sled::Db,
+    durability: Durability,
 }
 
 impl SledKvsEngine {
@@ -20,20 +22,55 @@ impl SledKvsEngine {
         SledKvsEngine::open(&cwd)
     }
 
-    /// Creates a new storage instance using `sled` as the storage engine at the given path
+    /// Creates a new storage instance using `sled` as the storage engine at the given path, with
+    /// the default `Durability`
     ///
     /// # Errors
     ///
     /// An error will occur if there is a problem starting the `sled` instance at the given path
     pub fn open(path: &path::Path) -> Result<SledKvsEngine> {
+        SledKvsEngine::open_with_durability(path, Durability::default())
+    }
+
+    /// Creates a new storage instance using `sled` as the storage engine at the given path, which
+    /// flushes writes to disk as `durability` asks
+    ///
+    /// # Errors
+    ///
+    /// - A `KvsError::BadOptionError` will occur if `durability` asks for a zero group commit
+    ///   interval
+    /// - Any other error will occur if there is a problem starting the `sled` instance at the
+    ///   given path
+    pub fn open_with_durability(
+        path: &path::Path,
+        durability: Durability,
+    ) -> Result<SledKvsEngine> {
+        durability.check()?;
         let db_path = path.join(path::Path::new("sled"));
-        let store = Db::start_default(db_path)?;
-        Ok(SledKvsEngine { store })
+        // sled flushes on a background thread of its own, which is exactly a group commit
+        let flush_every_ms = match durability {
+            Durability::GroupCommit(interval) => Some(interval.as_millis().max(1) as u64),
+            Durability::None | Durability::EveryWrite => None,
+        };
+        let config = ConfigBuilder::new()
+            .path(db_path)
+            .flush_every_ms(flush_every_ms)
+            .build();
+        let store = Db::start(config)?;
+        Ok(SledKvsEngine { store, durability })
+    }
+
+    /// Flushes a change to disk straight away if `Durability::EveryWrite` was asked for
+    fn commit(&self) -> Result<()> {
+        if self.durability == Durability::EveryWrite {
+            self.store.flush()?;
+        }
+        Ok(())
     }
 }
 
 impl KvsEngine for SledKvsEngine {
-    fn get(&mut self, key: String) -> Result<Option<String>> {
+    fn get(&self, key: String) -> Result<Option<String>> {
         match self.store.get(key)? {
             Some(value) => match str::from_utf8(value.as_ref()) {
                 Ok(value) => Ok(Some(value.to_owned())),
@@ -46,13 +83,12 @@ impl KvsEngine for SledKvsEngine {
         }
     }
 
-    fn set(&mut self, key: String, value: String) -> Result<()> {
+    fn set(&self, key: String, value: String) -> Result<()> {
         self.store.set(key, IVec::from(value.into_bytes()))?;
-        self.store.flush()?;
-        Ok(())
+        self.commit()
     }
 
-    fn remove(&mut self, key: String) -> Result<()> {
+    fn remove(&self, key: String) -> Result<()> {
         let rm_result = self.store.del(key);
         if let Ok(None) = rm_result {
             return Err(KvsError::BadRemovalError);
@@ -60,6 +96,10 @@ impl KvsEngine for SledKvsEngine {
         if let Err(err) = rm_result {
             return Err(KvsError::SledError(err));
         }
+        self.commit()
+    }
+
+    fn flush(&self) -> Result<()> {
         self.store.flush()?;
         Ok(())
     }
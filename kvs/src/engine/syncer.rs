@@ -0,0 +1,52 @@
+use super::kv::Shared;
+use crate::Result;
+use std::sync::{mpsc, Arc};
+use std::{thread, time};
+
+/// A handle to the background thread that flushes the active segment of a `KvStore` to disk for
+/// `Durability::GroupCommit`. Dropping the handle flushes one last time and stops the thread.
+pub struct Syncer {
+    sender: mpsc::Sender<()>,
+    handle: Option<thread::JoinHandle<()>>,
+}
+
+impl Syncer {
+    /// Starts a thread that flushes the store described by `shared` every `interval`
+    ///
+    /// # Errors
+    ///
+    /// A `KvsError::IoError` will occur if the thread cannot be started
+    pub fn spawn(shared: Arc<Shared>, interval: time::Duration) -> Result<Syncer> {
+        let (sender, receiver) = mpsc::channel();
+        let handle = thread::Builder::new()
+            .name("kvs-sync".to_owned())
+            .spawn(move || loop {
+                let stop = !matches!(
+                    receiver.recv_timeout(interval),
+                    Err(mpsc::RecvTimeoutError::Timeout)
+                );
+                if let Err(err) = shared.sync() {
+                    error!("Flushing the log to disk failed: {}", err);
+                }
+                if stop {
+                    break;
+                }
+            })?;
+
+        Ok(Syncer {
+            sender,
+            handle: Some(handle),
+        })
+    }
+}
+
+impl Drop for Syncer {
+    fn drop(&mut self) {
+        let _ = self.sender.send(());
+        if let Some(handle) = self.handle.take() {
+            if handle.join().is_err() {
+                error!("Sync thread panicked");
+            }
+        }
+    }
+}
@@ -1,3 +1,5 @@
+use crate::ErrorCode;
+
 /// Alias for `Result<T, E>` where `E: KvsError`
 pub type Result<T> = std::result::Result<T, KvsError>;
 
@@ -19,15 +21,60 @@ pub enum KvsError {
     /// An error occured because the requested initialization path was not a directory
     #[fail(display = "The initialization path must be a directory: {}", _0)]
     BadPathError(String),
+    /// An error occured because an option given to the store is out of range
+    #[fail(display = "Invalid store option: {}", _0)]
+    BadOptionError(String),
+    /// An error occured because no store exists at the requested path and none may be created
+    #[fail(display = "No store exists at: {}", _0)]
+    StoreMissingError(String),
+    /// An error occured because a store already exists at the requested path
+    #[fail(display = "A store already exists at: {}", _0)]
+    StoreExistsError(String),
+    /// An error occured because a change was requested from a store opened read-only
+    #[fail(display = "The store was opened read-only")]
+    ReadOnlyError,
     /// An error occured while trying to parse the provided address string
     #[fail(display = "Bad address string: {}", _0)]
     BadAddressError(String),
+    /// An error occured because the client and server have no protocol version in common
+    #[fail(display = "Incompatible protocol version: {}", _0)]
+    ProtocolVersionError(String),
+    /// An error occured while setting up a connection between a client and a server
+    #[fail(display = "The connection handshake failed: {}", _0)]
+    HandshakeError(String),
+    /// An error occured because a message received over the network could not be decoded. The
+    /// number of bytes received for the message is provided.
+    #[fail(display = "Failed to decode a message of {} bytes: {}", _1, _0)]
+    DecodeError(String, usize),
+    /// An error occured because the other side of a connection broke the wire protocol
+    #[fail(display = "Protocol error: {}", _0)]
+    ProtocolError(String),
+    /// An error occured on the server while carrying out a request, which has no closer match
+    /// among these errors. Unlike a `KvsError::IoError`, it is no fault of the connection, even
+    /// when a file operation failed on the server.
+    #[fail(display = "The server failed: {}", message)]
+    ServerError {
+        /// What kind of failure the server reported
+        code: ErrorCode,
+        /// The server's description of the failure
+        message: String,
+    },
     /// An error occured due to a `std::io::Error`
     #[fail(display = "An io error occured: {}", _0)]
     IoError(#[cause] std::io::Error),
     /// An error occured while trying to serialize or deserialize a command
     #[fail(display = "A serialization error occured: {}", _0)]
     SerdeError(#[cause] serde_json::Error),
+    /// An error occured because a log record failed its checksum or was cut short. The segment id
+    /// and the offset of the damaged record are provided.
+    #[fail(display = "The log is corrupt in segment {} at offset {}", _0, _1)]
+    CorruptionError(u64, u64),
+    /// An error occured because a log segment was written in an unsupported format version
+    #[fail(display = "Unsupported log format version: {}", _0)]
+    FormatVersionError(u32),
+    /// An error occured while encoding or decoding a binary log record
+    #[fail(display = "A binary encoding error occured: {}", _0)]
+    BincodeError(#[cause] bincode::Error),
     /// An error occured while using the `sled` engine
     #[fail(display = "A sled error occured: {}", _0)]
     SledError(#[cause] sled::Error),
@@ -45,6 +92,12 @@ impl From<serde_json::Error> for KvsError {
     }
 }
 
+impl From<bincode::Error> for KvsError {
+    fn from(err: bincode::Error) -> KvsError {
+        KvsError::BincodeError(err)
+    }
+}
+
 impl From<sled::Error> for KvsError {
     fn from(err: sled::Error) -> KvsError {
         KvsError::SledError(err)
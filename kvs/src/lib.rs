@@ -1,4 +1,6 @@
 #![deny(missing_docs)]
+// `failure_derive` expands `#[derive(Fail)]` into impls nested inside a named constant
+#![allow(non_local_definitions)]
 
 //! `kvs` is a key-value storage library that provides a filesystem-backed key-value store called
 //! `KvStore`. The storage interface is generic and provides options for using other key-value
@@ -18,10 +20,23 @@ extern crate failure_derive;
 #[macro_use]
 extern crate log;
 
-pub use engine::{KvStore, KvsEngine, SledKvsEngine};
+pub use engine::{
+    CompactionProgress, CompactionThreshold, Durability, KvStore, KvStoreOptions, KvsEngine,
+    SledKvsEngine,
+};
+#[cfg(feature = "async")]
+pub use engine::AsyncKvsEngine;
 pub use error::{KvsError, Result};
-pub use net::{KvsClient, KvsServer, KvsRequest, KvsResponse};
+#[cfg(feature = "async")]
+pub use net::{AsyncKvsClient, AsyncKvsServer};
+pub use net::{
+    Capabilities, ErrorCode, Hello, HelloReply, KvsAddr, KvsClient, KvsClientOptions, KvsServer,
+    KvsRequest, KvsResponse, RequestEnvelope, ResponseEnvelope, Session, ShutdownHandle,
+    MIN_PROTOCOL_VERSION, PROTOCOL_VERSION, UNKNOWN_REQUEST_ID,
+};
+pub use thread_pool::{NaiveThreadPool, RayonThreadPool, SharedQueueThreadPool, ThreadPool};
 
 mod engine;
 mod net;
 mod error;
+mod thread_pool;
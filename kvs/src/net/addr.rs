@@ -0,0 +1,90 @@
+use crate::KvsError;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::{fmt, str};
+
+/// The prefix that marks an address as the path of a Unix domain socket
+const UNIX_PREFIX: &str = "unix:";
+
+/// The address of a `KvsServer`: either a TCP socket address or, on Unix, the path of a Unix
+/// domain socket
+///
+/// Parsed from `host:port` (the host may be a name, which is resolved to its first address) or
+/// from `unix:/path/to/socket`. A Unix domain socket can only be reached from the same host, and
+/// who may connect to it is decided by the permissions of the socket file.
+///
+/// # Example
+///
+/// ```
+/// use kvs::KvsAddr;
+/// let tcp: KvsAddr = "127.0.0.1:4000".parse().unwrap();
+/// assert_eq!(tcp, KvsAddr::Tcp("127.0.0.1:4000".parse().unwrap()));
+/// # #[cfg(unix)]
+/// # {
+/// let unix: KvsAddr = "unix:/tmp/kvs.sock".parse().unwrap();
+/// assert_eq!(unix.to_string(), "unix:/tmp/kvs.sock");
+/// # }
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum KvsAddr {
+    /// A TCP socket address
+    Tcp(SocketAddr),
+    /// The path of a Unix domain socket
+    #[cfg(unix)]
+    Unix(std::path::PathBuf),
+}
+
+impl From<SocketAddr> for KvsAddr {
+    fn from(addr: SocketAddr) -> KvsAddr {
+        KvsAddr::Tcp(addr)
+    }
+}
+
+impl str::FromStr for KvsAddr {
+    type Err = KvsError;
+
+    /// Parses `host:port` or `unix:/path`
+    ///
+    /// # Errors
+    ///
+    /// A `KvsError::BadAddressError` will occur if the address is neither, if its host cannot
+    /// be resolved, or if it names a Unix domain socket on a platform without them
+    fn from_str(addr: &str) -> Result<KvsAddr, KvsError> {
+        if let Some(path) = addr.strip_prefix(UNIX_PREFIX) {
+            return unix_addr(path);
+        }
+        addr.to_socket_addrs()
+            .ok()
+            .and_then(|mut addrs| addrs.next())
+            .map(KvsAddr::Tcp)
+            .ok_or_else(|| KvsError::BadAddressError(addr.to_owned()))
+    }
+}
+
+impl fmt::Display for KvsAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KvsAddr::Tcp(addr) => write!(f, "{}", addr),
+            #[cfg(unix)]
+            KvsAddr::Unix(path) => write!(f, "{}{}", UNIX_PREFIX, path.display()),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn unix_addr(path: &str) -> Result<KvsAddr, KvsError> {
+    if path.is_empty() {
+        return Err(KvsError::BadAddressError(format!(
+            "{}{}",
+            UNIX_PREFIX, path
+        )));
+    }
+    Ok(KvsAddr::Unix(std::path::PathBuf::from(path)))
+}
+
+#[cfg(not(unix))]
+fn unix_addr(path: &str) -> Result<KvsAddr, KvsError> {
+    Err(KvsError::BadAddressError(format!(
+        "{}{} (Unix domain sockets are not supported on this platform)",
+        UNIX_PREFIX, path
+    )))
+}
@@ -0,0 +1,121 @@
+use super::client::unexpected_response;
+use super::frame::{read_frame_async, write_frame_async};
+use crate::{
+    Capabilities, Hello, HelloReply, KvsError, KvsRequest, KvsResponse, RequestEnvelope,
+    ResponseEnvelope, Result,
+};
+use std::net::SocketAddr;
+use tokio::io::{AsyncWriteExt, BufStream};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+
+/// A client for interacting with a remote key-value store from a tokio runtime
+///
+/// Like `KvsClient`, the client keeps its connection open across requests. Requests sent from
+/// several tasks at once take turns on the connection.
+pub struct AsyncKvsClient {
+    addr: SocketAddr,
+    connection: Mutex<Option<Connection>>,
+}
+
+/// An open connection to the server
+struct Connection {
+    stream: BufStream<TcpStream>,
+    /// The id given to the next request sent on the connection
+    next_id: u64,
+}
+
+impl AsyncKvsClient {
+    /// Creates a new client ready to connect at the given address
+    ///
+    /// # Arguments
+    ///
+    /// - addr - the address of the server
+    pub fn new(addr: SocketAddr) -> AsyncKvsClient {
+        AsyncKvsClient {
+            addr,
+            connection: Mutex::new(None),
+        }
+    }
+
+    /// Retrieves the value for a given key from the server. See `KvsClient::get`.
+    pub async fn get(&self, key: String) -> Result<Option<String>> {
+        match self.send(KvsRequest::Get { key }).await? {
+            KvsResponse::Get { value } => Ok(value),
+            response => Err(unexpected_response(response)),
+        }
+    }
+
+    /// Sets a value for a given key on the server. See `KvsClient::set`.
+    pub async fn set(&self, key: String, value: String) -> Result<()> {
+        match self.send(KvsRequest::Set { key, value }).await? {
+            KvsResponse::Set => Ok(()),
+            response => Err(unexpected_response(response)),
+        }
+    }
+
+    /// Removes a key-value relationship on the server. See `KvsClient::remove`.
+    pub async fn remove(&self, key: String) -> Result<()> {
+        match self.send(KvsRequest::Remove { key }).await? {
+            KvsResponse::Remove => Ok(()),
+            response => Err(unexpected_response(response)),
+        }
+    }
+
+    /// Sends a request to the server at the stored address
+    ///
+    /// # Arguments
+    ///
+    /// - request - a request to send to the server
+    ///
+    /// # Errors
+    ///
+    /// - An error may occur due to a failure to connect to the server,
+    ///   problems with serialization/deserialization, or other networking errors
+    /// - If the server answers with a `KvsResponse::Failure`, the `KvsError` its `ErrorCode`
+    ///   stands for is returned
+    pub async fn send(&self, request: KvsRequest) -> Result<KvsResponse> {
+        let mut connection = self.connection.lock().await;
+        if connection.is_none() {
+            *connection = Some(Connection::open(self.addr).await?);
+        }
+        let result = exchange(connection.as_mut().unwrap(), request).await;
+        if result.is_err() {
+            *connection = None;
+        }
+        result
+    }
+}
+
+impl Connection {
+    /// Connects to the server and goes through the handshake. Only one request is ever in flight,
+    /// so the client asks for no capabilities.
+    async fn open(addr: SocketAddr) -> Result<Connection> {
+        let stream = TcpStream::connect(addr).await?;
+        stream.set_nodelay(true)?;
+        let mut stream = BufStream::new(stream);
+        write_frame_async(&mut stream, &Hello::new(Capabilities::empty())).await?;
+        stream.flush().await?;
+        read_frame_async::<_, HelloReply>(&mut stream)
+            .await?
+            .ok_or_else(|| KvsError::HandshakeError("The server closed the connection".to_owned()))?
+            .into_session()?;
+        Ok(Connection { stream, next_id: 0 })
+    }
+}
+
+/// Writes a request and waits for its response
+async fn exchange(connection: &mut Connection, request: KvsRequest) -> Result<KvsResponse> {
+    let id = connection.next_id;
+    connection.next_id += 1;
+    let stream = &mut connection.stream;
+    write_frame_async(stream, &RequestEnvelope { id, request }).await?;
+    stream.flush().await?;
+    let envelope: ResponseEnvelope = read_frame_async(stream)
+        .await?
+        .ok_or_else(|| KvsError::InternalError("The server closed the connection".to_owned()))?;
+    if envelope.id != id {
+        return Err(envelope.into_unexpected());
+    }
+    envelope.response.into_result()
+}
@@ -0,0 +1,271 @@
+use super::frame::{decode_frame, read_frame_async, read_frame_bytes_async, write_frame_async};
+use super::server::malformed_request;
+use super::shutdown::ShutdownHandle;
+use crate::{
+    AsyncKvsEngine, Capabilities, Hello, HelloReply, KvsEngine, KvsError, KvsRequest, KvsResponse,
+    RequestEnvelope, ResponseEnvelope, Result, UNKNOWN_REQUEST_ID,
+};
+use std::future::Future;
+use std::net::{self, SocketAddr};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::io::{AsyncWriteExt, BufReader, BufWriter};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, watch, Semaphore};
+use tokio::time;
+
+/// How many requests of one connection may be carried out or waiting for their responses to be
+/// written at once. Past this, the server stops reading requests from the connection.
+const MAX_IN_FLIGHT: u32 = 64;
+
+/// A server for hosting a key value store on a tokio runtime
+///
+/// Every connection is a task rather than a thread, so a handful of runtime threads can serve
+/// many thousands of connections, idle or not. Engine calls go through an `AsyncKvsEngine`.
+pub struct AsyncKvsServer<E: KvsEngine> {
+    addr: SocketAddr,
+    /// The listener bound by `AsyncKvsServer::bind`, if the server was created that way
+    listener: Option<net::TcpListener>,
+    engine: AsyncKvsEngine<E>,
+    shutdown: ShutdownHandle,
+}
+
+impl<E: KvsEngine> AsyncKvsServer<E> {
+    /// Creates a new server ready to accept key-value requests. The address is only bound once
+    /// the server starts serving.
+    ///
+    /// # Arguments
+    ///
+    /// - addr - the address to bind to
+    /// - engine - the engine to use for storage
+    pub fn new(addr: SocketAddr, engine: E) -> AsyncKvsServer<E> {
+        AsyncKvsServer {
+            addr,
+            listener: None,
+            engine: AsyncKvsEngine::new(engine),
+            shutdown: ShutdownHandle::default(),
+        }
+    }
+
+    /// Creates a new server and binds its address straight away. See `KvsServer::bind`. Unlike
+    /// `serve`, this does not need a tokio runtime.
+    ///
+    /// # Errors
+    ///
+    /// An error may occur if there is a problem binding to the bind address
+    pub fn bind(addr: SocketAddr, engine: E) -> Result<AsyncKvsServer<E>> {
+        let listener = net::TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        let mut server = AsyncKvsServer::new(listener.local_addr()?, engine);
+        server.listener = Some(listener);
+        Ok(server)
+    }
+
+    /// The address the server listens on. See `KvsServer::local_addr`.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Returns a handle that shuts the server down
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Waits for incoming connections until the server is shut down through a `ShutdownHandle`.
+    /// Each connection is handled on a task of its own for as long as the client keeps it open.
+    /// Must be run within a tokio runtime, and since the future does not borrow the server it can
+    /// be spawned as a task itself.
+    ///
+    /// Once shut down, the server waits for the requests in flight (see `ShutdownHandle`) and
+    /// flushes the engine to disk before the future completes. Dropping the future instead stops
+    /// the server straight away, closing its connections without flushing the engine.
+    ///
+    /// # Errors
+    ///
+    /// - An error may occur if there is a problem binding to the bind address
+    /// - An error may occur if flushing the engine fails
+    pub fn serve(&self) -> impl Future<Output = Result<()>> {
+        let addr = self.addr;
+        let bound = self.listener.as_ref().map(net::TcpListener::try_clone);
+        let engine = self.engine.clone();
+        let shutdown = self.shutdown.clone();
+        async move {
+            let listener = match bound {
+                Some(listener) => TcpListener::from_std(listener?)?,
+                None => TcpListener::bind(addr).await?,
+            };
+            let mut stopping = shutdown.stopping();
+            // every connection's task holds a clone of `tracker`, so `done` closes once they end
+            let (tracker, mut done) = mpsc::channel::<()>(1);
+            let (close, closing) = watch::channel(false);
+            loop {
+                let (mut stream, peer) = tokio::select! {
+                    biased;
+                    _ = stopping.changed() => break,
+                    accepted = listener.accept() => match accepted {
+                        Ok(accepted) => accepted,
+                        Err(err) => {
+                            warn!("Failed while accepting stream: {}", err);
+                            continue;
+                        }
+                    },
+                };
+                info!("New connection from {}", peer.ip());
+                let engine = engine.clone();
+                let stopping = shutdown.stopping();
+                let mut closing = closing.clone();
+                let tracker = tracker.clone();
+                tokio::spawn(async move {
+                    let _tracker = tracker;
+                    tokio::select! {
+                        result = handle_connection(engine, &mut stream, stopping) => {
+                            if let Err(err) = result {
+                                error!("Failed while serving connection: {}", err);
+                            }
+                        }
+                        // the grace period ran out, or the server future was dropped
+                        _ = closing.changed() => {}
+                    }
+                    if let Err(err) = stream.shutdown().await {
+                        warn!("Failed to close socket: {}", err);
+                    }
+                });
+            }
+            drop(listener);
+            drop(tracker);
+            let deadline = shutdown.deadline().unwrap_or_else(Instant::now);
+            if time::timeout_at(deadline.into(), done.recv())
+                .await
+                .is_err()
+            {
+                warn!("Closing connections that outlived the shutdown grace period");
+                let _ = close.send(true);
+                done.recv().await;
+            }
+            info!("Flushing the engine");
+            engine.flush().await
+        }
+    }
+}
+
+/// Answers the handshake on a connection and then its requests, until the client closes it or
+/// `stopping` says the server is shutting down. Requests already read still get their responses.
+/// Requests take effect in the order they arrive. If the client accepts responses out of order,
+/// a run of pipelined `get`s is carried out concurrently and their responses are written as soon
+/// as they are ready, but a change waits for every request before it to finish and is finished
+/// before any request after it starts.
+///
+/// Malformed requests are answered with an error just as `KvsServer` does.
+async fn handle_connection<E: KvsEngine>(
+    engine: AsyncKvsEngine<E>,
+    stream: &mut TcpStream,
+    mut stopping: watch::Receiver<bool>,
+) -> Result<()> {
+    let (reader, writer) = stream.split();
+    let mut reader = BufReader::new(reader);
+    let mut writer = BufWriter::new(writer);
+    let reply = match read_frame_async::<_, Hello>(&mut reader).await {
+        Ok(Some(hello)) => {
+            hello.accept(Capabilities::PIPELINING | Capabilities::UNORDERED_RESPONSES)
+        }
+        Ok(None) => return Ok(()),
+        Err(err @ KvsError::DecodeError(..)) => {
+            HelloReply::rejected(format!("Expected a handshake: {}", err))
+        }
+        Err(err) => return Err(err),
+    };
+    write_frame_async(&mut writer, &reply).await?;
+    writer.flush().await?;
+    let (version, unordered) = match reply {
+        HelloReply::Accepted {
+            version,
+            capabilities,
+        } => (
+            version,
+            capabilities.contains(Capabilities::UNORDERED_RESPONSES),
+        ),
+        HelloReply::Rejected { message, .. } => {
+            warn!("Rejected connection: {}", message);
+            return Ok(());
+        }
+    };
+
+    let (sender, mut receiver) = mpsc::channel(MAX_IN_FLIGHT as usize);
+    let in_flight = Arc::new(Semaphore::new(MAX_IN_FLIGHT as usize));
+    let read = async move {
+        loop {
+            let message = tokio::select! {
+                biased;
+                _ = stopping.changed() => break,
+                message = read_frame_bytes_async(&mut reader) => message,
+            };
+            let (id, request) = match message {
+                Ok(Some(message)) => match decode_frame(&message) {
+                    Ok(RequestEnvelope { id, request }) => (id, request),
+                    Err(err) => {
+                        let id = RequestEnvelope::peek_id(&message);
+                        let _ = sender.send(malformed_request(id, &err)).await;
+                        continue;
+                    }
+                },
+                Ok(None) => break,
+                Err(err @ KvsError::DecodeError(..)) => {
+                    let _ = sender
+                        .send(malformed_request(UNKNOWN_REQUEST_ID, &err))
+                        .await;
+                    break;
+                }
+                Err(err) => return Err(err),
+            };
+            if !unordered || !matches!(request, KvsRequest::Get { .. }) {
+                // waits for the gets still running, so the change cannot overtake them
+                let _all = in_flight.acquire_many(MAX_IN_FLIGHT).await;
+                let response = handle_request(engine.clone(), request).await;
+                let _ = sender.send(ResponseEnvelope { id, response }).await;
+                continue;
+            }
+            let permit = in_flight.clone().acquire_owned().await;
+            let engine = engine.clone();
+            let sender = sender.clone();
+            tokio::spawn(async move {
+                let response = handle_request(engine, request).await;
+                // the connection only goes away before the response is sent if writing failed
+                let _ = sender.send(ResponseEnvelope { id, response }).await;
+                drop(permit);
+            });
+        }
+        Ok::<(), KvsError>(())
+    };
+    let write = async move {
+        while let Some(envelope) = receiver.recv().await {
+            write_frame_async(&mut writer, &envelope.for_version(version)).await?;
+            while let Ok(envelope) = receiver.try_recv() {
+                write_frame_async(&mut writer, &envelope.for_version(version)).await?;
+            }
+            writer.flush().await?;
+        }
+        Ok::<(), KvsError>(())
+    };
+    tokio::try_join!(read, write)?;
+    Ok(())
+}
+
+async fn handle_request<E: KvsEngine>(
+    engine: AsyncKvsEngine<E>,
+    request: KvsRequest,
+) -> KvsResponse {
+    match request {
+        KvsRequest::Get { key } => match engine.get(key).await {
+            Ok(value) => KvsResponse::Get { value },
+            Err(err) => KvsResponse::error(&err),
+        },
+        KvsRequest::Remove { key } => match engine.remove(key).await {
+            Ok(_) => KvsResponse::Remove {},
+            Err(err) => KvsResponse::error(&err),
+        },
+        KvsRequest::Set { key, value } => match engine.set(key, value).await {
+            Ok(_) => KvsResponse::Set {},
+            Err(err) => KvsResponse::error(&err),
+        },
+    }
+}
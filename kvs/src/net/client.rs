@@ -1,22 +1,134 @@
 extern crate bincode;
 
-use crate::{KvsRequest, KvsResponse, Result};
-use std::io::{Read, Write};
-use std::net::{Shutdown, SocketAddr, TcpStream};
+use super::addr::KvsAddr;
+use super::frame::{read_frame, write_frame};
+use super::options::KvsClientOptions;
+use super::pool::Pool;
+use super::transport::Stream;
+use crate::{
+    Capabilities, Hello, HelloReply, KvsEngine, KvsError, KvsRequest, KvsResponse, RequestEnvelope,
+    ResponseEnvelope, Result, Session,
+};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::Shutdown;
+use std::sync::Arc;
+use std::{cmp, thread};
 
 /// A client for interacting with a remote key-value store
+///
+/// The client keeps a pool of open connections: each request takes an idle connection from the
+/// pool (or opens a new one if there is none) and puts it back once its response has arrived, so
+/// only the first requests pay for a TCP handshake. Idle connections the server has closed are
+/// noticed and dropped before they are used, and a connection is dropped for good if a request on
+/// it fails. Connecting, reading and writing all give up after the timeouts in the client's
+/// `KvsClientOptions`, and a `get` that fails to reach the server is retried.
+///
+/// Besides sending one request at a time, the client can pipeline a batch of requests: the whole
+/// batch is sent without waiting for any responses, which are then matched back to their requests
+/// by id. Whether the server allows this is settled in a handshake when the connection is
+/// opened; if it does not, the batch is sent one request at a time instead.
+///
+/// The client implements `KvsEngine`, so code written against the trait can work with a remote
+/// store just as well as with a local one. Clones of the client share its pool of connections.
+///
+/// # Example
+///
+/// ```no_run
+/// use kvs::KvsClient;
+/// let client = KvsClient::new("127.0.0.1:4000".parse().unwrap());
+/// client.set("key".to_owned(), "value".to_owned())?;
+/// assert_eq!(client.get("key".to_owned())?, Some("value".to_owned()));
+/// # Ok::<(), kvs::KvsError>(())
+/// ```
+#[derive(Clone)]
 pub struct KvsClient {
-    addr: SocketAddr,
+    addr: KvsAddr,
+    options: KvsClientOptions,
+    pool: Arc<Pool>,
+}
+
+/// Both directions of an open connection to the server
+pub(super) struct Connection {
+    reader: BufReader<Stream>,
+    writer: BufWriter<Stream>,
+    /// What the handshake settled on
+    session: Session,
+    /// The id given to the next request sent on the connection
+    next_id: u64,
 }
 
 impl KvsClient {
-    /// Creates a new client ready to connect at the given address
+    /// Creates a new client ready to connect at the given address, with the default
+    /// `KvsClientOptions`
     ///
     /// # Arguments
     ///
-    /// - addr - the address of the server
-    pub fn new(addr: SocketAddr) -> KvsClient {
-        KvsClient { addr }
+    /// - addr - the address of the server, a TCP address or a Unix domain socket
+    pub fn new(addr: KvsAddr) -> KvsClient {
+        KvsClient::with_options(addr, KvsClientOptions::new())
+    }
+
+    /// Creates a new client ready to connect at the given address, with the given options
+    pub fn with_options(addr: KvsAddr, options: KvsClientOptions) -> KvsClient {
+        KvsClient {
+            addr,
+            pool: Arc::new(Pool::new(options.max_idle_connections)),
+            options,
+        }
+    }
+
+    /// Connects to the server if the client is not connected yet, and returns what the handshake
+    /// settled on
+    ///
+    /// # Errors
+    ///
+    /// - A `KvsError::ProtocolVersionError` will occur if the server speaks none of the protocol
+    ///   versions the client does
+    /// - A `KvsError::HandshakeError` will occur if the server rejects the connection otherwise
+    /// - A `KvsError::IoError` will occur if the server cannot be reached
+    pub fn session(&self) -> Result<Session> {
+        let connection = self.checkout()?;
+        let session = connection.session;
+        self.pool.put(connection);
+        Ok(session)
+    }
+
+    /// Retrieves the value for a given key from the server. If the server cannot be reached or
+    /// the connection fails, the request is retried as `KvsClientOptions::retries` allows.
+    ///
+    /// # Errors
+    ///
+    /// See `KvsClient::send`. The error of the last attempt is returned.
+    pub fn get(&self, key: String) -> Result<Option<String>> {
+        self.retry(|| match self.send(KvsRequest::Get { key: key.clone() })? {
+            KvsResponse::Get { value } => Ok(value),
+            response => Err(unexpected_response(response)),
+        })
+    }
+
+    /// Sets a value for a given key on the server
+    ///
+    /// # Errors
+    ///
+    /// See `KvsClient::send`
+    pub fn set(&self, key: String, value: String) -> Result<()> {
+        match self.send(KvsRequest::Set { key, value })? {
+            KvsResponse::Set => Ok(()),
+            response => Err(unexpected_response(response)),
+        }
+    }
+
+    /// Removes a key-value relationship on the server
+    ///
+    /// # Errors
+    ///
+    /// - A `KvsError::BadRemovalError` will occur if the requested key was not found
+    /// - See `KvsClient::send` for the rest
+    pub fn remove(&self, key: String) -> Result<()> {
+        match self.send(KvsRequest::Remove { key })? {
+            KvsResponse::Remove => Ok(()),
+            response => Err(unexpected_response(response)),
+        }
     }
 
     /// Sends a request to the server at the stored address
@@ -27,18 +139,234 @@ impl KvsClient {
     ///
     /// # Errors
     ///
-    /// An error may occur due to a failure to connect to the server,
-    /// problems with serialization/deserialization, or other networking errors
+    /// - An error may occur due to a failure to connect to the server,
+    ///   problems with serialization/deserialization, or other networking errors
+    /// - A `KvsError::IoError` will occur if connecting, reading or writing takes longer than
+    ///   its timeout
+    /// - If the server answers with a `KvsResponse::Failure`, the `KvsError` its `ErrorCode`
+    ///   stands for is returned (`KvsError::BadRemovalError` for a key that does not exist, for
+    ///   instance)
     pub fn send(&self, request: KvsRequest) -> Result<KvsResponse> {
-        let mut stream = TcpStream::connect(self.addr)?;
+        let mut response = None;
+        self.send_all_unordered(vec![request], |_, received| response = Some(received))?;
+        response.unwrap_or(Err(KvsError::UnknownError))
+    }
 
-        let serialized = bincode::serialize(&request).expect("Failed to serialize request");
-        stream.write_all(&serialized)?;
-        stream.shutdown(Shutdown::Write)?;
+    /// Pipelines a batch of requests to the server, returning the outcome of each request in the
+    /// same order as the requests. Requests the server failed to carry out have the error their
+    /// `ErrorCode` stands for as their outcome, without failing the rest of the batch.
+    ///
+    /// # Errors
+    ///
+    /// See `KvsClient::send`. Anything other than an error response fails the whole batch,
+    /// although requests sent before the failure may have been carried out by the server.
+    pub fn send_all(&self, requests: Vec<KvsRequest>) -> Result<Vec<Result<KvsResponse>>> {
+        let mut responses: Vec<Option<Result<KvsResponse>>> =
+            requests.iter().map(|_| None).collect();
+        self.send_all_unordered(requests, |index, response| {
+            responses[index] = Some(response)
+        })?;
+        Ok(responses.into_iter().flatten().collect())
+    }
 
-        let mut read_buf = Vec::new();
-        stream.read_to_end(&mut read_buf)?;
+    /// Pipelines a batch of requests to the server, calling `on_response` with the position of
+    /// the request in `requests` and its outcome as each response arrives. Responses arrive in
+    /// the order the requests were sent, unless `KvsClientOptions::unordered_responses` allows
+    /// the server to answer them as it finishes them.
+    ///
+    /// # Errors
+    ///
+    /// See `KvsClient::send_all`
+    pub fn send_all_unordered<F>(&self, requests: Vec<KvsRequest>, mut on_response: F) -> Result<()>
+    where
+        F: FnMut(usize, Result<KvsResponse>),
+    {
+        let on_response = |index, response: KvsResponse| on_response(index, response.into_result());
+        let mut connection = self.checkout()?;
+        connection.exchange(requests, on_response)?;
+        self.pool.put(connection);
+        Ok(())
+    }
+
+    /// Takes an idle connection from the pool, or opens a new one
+    fn checkout(&self) -> Result<Connection> {
+        match self.pool.take() {
+            Some(connection) => Ok(connection),
+            None => Connection::open(&self.addr, &self.options),
+        }
+    }
+
+    /// Calls `call` until it succeeds, fails for a reason a retry cannot fix, or runs out of
+    /// retries, waiting longer after each failure
+    fn retry<T, F>(&self, mut call: F) -> Result<T>
+    where
+        F: FnMut() -> Result<T>,
+    {
+        let mut backoff = self.options.backoff;
+        for _ in 0..self.options.retries {
+            match call() {
+                Err(ref err) if is_transient(err) => {
+                    warn!("Request failed, retrying in {:?}: {}", backoff, err);
+                    thread::sleep(backoff);
+                    backoff = cmp::min(backoff * 2, self.options.max_backoff);
+                }
+                result => return result,
+            }
+        }
+        call()
+    }
+}
+
+impl KvsEngine for KvsClient {
+    fn get(&self, key: String) -> Result<Option<String>> {
+        KvsClient::get(self, key)
+    }
+
+    fn set(&self, key: String, value: String) -> Result<()> {
+        KvsClient::set(self, key, value)
+    }
+
+    fn remove(&self, key: String) -> Result<()> {
+        KvsClient::remove(self, key)
+    }
+
+    /// Does nothing: the server flushes changes as its own `Durability` asks, and flushes
+    /// everything when it shuts down
+    fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl Connection {
+    fn open(addr: &KvsAddr, options: &KvsClientOptions) -> Result<Connection> {
+        let stream = Stream::connect(addr, options.connect_timeout)?;
+        stream.set_timeouts(options.read_timeout, options.write_timeout)?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = BufWriter::new(stream);
 
-        Ok(bincode::deserialize::<KvsResponse>(&read_buf).expect("Failed to deserialize request"))
+        let mut capabilities = Capabilities::PIPELINING;
+        if options.unordered_responses {
+            capabilities = capabilities | Capabilities::UNORDERED_RESPONSES;
+        }
+        let hello = Hello::new(capabilities);
+        write_frame(&mut writer, &hello)?;
+        writer.flush()?;
+        let session = read_frame::<_, HelloReply>(&mut reader)?
+            .ok_or_else(|| KvsError::HandshakeError("The server closed the connection".to_owned()))?
+            .into_session()?;
+        Ok(Connection {
+            reader,
+            writer,
+            session,
+            next_id: 0,
+        })
     }
+
+    /// Checks that an idle connection can still be used: the server has not closed it, and has
+    /// not sent anything unasked for
+    pub(super) fn is_healthy(&self) -> bool {
+        if !self.reader.buffer().is_empty() {
+            return false;
+        }
+        let mut stream = self.reader.get_ref();
+        if stream.set_nonblocking(true).is_err() {
+            return false;
+        }
+        // anything read means the connection is no use any more, so nothing is lost by reading
+        let mut byte = [0];
+        let idle = match stream.read(&mut byte) {
+            Err(err) => err.kind() == io::ErrorKind::WouldBlock,
+            // the server closed the connection, or wrote to it
+            Ok(_) => false,
+        };
+        stream.set_nonblocking(false).is_ok() && idle
+    }
+
+    /// Writes a batch of requests and waits for all of their responses. A batch of more than one
+    /// request is written on a thread of its own, since a batch too large for the socket buffers
+    /// would otherwise have the client waiting for the server to read requests while the server
+    /// waits for the client to read responses.
+    fn exchange<F>(&mut self, requests: Vec<KvsRequest>, mut on_response: F) -> Result<()>
+    where
+        F: FnMut(usize, KvsResponse),
+    {
+        let first_id = self.next_id;
+        let count = requests.len();
+        self.next_id += count as u64;
+
+        let Connection {
+            reader,
+            writer,
+            session,
+            ..
+        } = self;
+        if count <= 1 || !session.capabilities.contains(Capabilities::PIPELINING) {
+            for (index, (id, request)) in (first_id..).zip(requests).enumerate() {
+                send_requests(writer, id, vec![request])?;
+                receive_responses(reader, id, 1, |_, response| on_response(index, response))?;
+            }
+            return Ok(());
+        }
+        thread::scope(|scope| {
+            let sending = scope.spawn(move || send_requests(writer, first_id, requests));
+            let received = receive_responses(reader, first_id, count, on_response);
+            if received.is_err() {
+                // unblocks the sending thread if the server has stopped reading
+                let _ = reader.get_ref().shutdown(Shutdown::Both);
+            }
+            let sent = sending
+                .join()
+                .unwrap_or_else(|_| Err(KvsError::InternalError("Sending panicked".to_owned())));
+            sent.and(received)
+        })
+    }
+}
+
+fn send_requests<W: Write>(writer: &mut W, first_id: u64, requests: Vec<KvsRequest>) -> Result<()> {
+    for (id, request) in (first_id..).zip(requests) {
+        write_frame(writer, &RequestEnvelope { id, request })?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Reads the responses to the `count` requests numbered from `first_id`, in any order
+fn receive_responses<R, F>(
+    reader: &mut R,
+    first_id: u64,
+    count: usize,
+    mut on_response: F,
+) -> Result<()>
+where
+    R: Read,
+    F: FnMut(usize, KvsResponse),
+{
+    let mut answered = vec![false; count];
+    for _ in 0..count {
+        let envelope: ResponseEnvelope = read_frame(reader)?.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "The server closed the connection",
+            )
+        })?;
+        let index = envelope.id.wrapping_sub(first_id) as usize;
+        if index >= count || answered[index] {
+            return Err(envelope.into_unexpected());
+        }
+        answered[index] = true;
+        on_response(index, envelope.response);
+    }
+    Ok(())
+}
+
+/// The error for a response of a different kind than the request it answers
+pub(super) fn unexpected_response(response: KvsResponse) -> KvsError {
+    KvsError::ProtocolError(format!("Unexpected response {:?}", response))
+}
+
+/// Whether a request that failed with `err` may succeed if it is sent again: the connection
+/// failed, rather than the server turning the request down. Failures on the server, even of its
+/// own file operations, arrive as `KvsError::ServerError` and are never retried.
+fn is_transient(err: &KvsError) -> bool {
+    matches!(err, KvsError::IoError(_) | KvsError::DecodeError(..))
 }
@@ -0,0 +1,89 @@
+use crate::KvsError;
+use serde::{Deserialize, Serialize};
+
+/// A stable, machine-readable description of why a request failed on the server, sent along with
+/// every `KvsResponse::Failure`
+///
+/// Codes travel as plain numbers which never change meaning. A code this build does not know
+/// (sent by a newer server) decodes as `ErrorCode::Unknown`, so new codes can be added without
+/// breaking older clients.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "u32", into = "u32")]
+pub enum ErrorCode {
+    /// A code this build does not know about
+    Unknown,
+    /// The key to remove does not exist (`KvsError::BadRemovalError`)
+    KeyNotFound,
+    /// The store was opened read-only (`KvsError::ReadOnlyError`)
+    ReadOnly,
+    /// A file operation failed on the server (`KvsError::IoError` there, but a
+    /// `KvsError::ServerError` for the client, whose connection is fine)
+    Io,
+    /// The server found its store damaged or written in an unknown format
+    Corruption,
+    /// The server could not read the request (`KvsError::ProtocolError`)
+    MalformedRequest,
+    /// Any other failure on the server
+    Internal,
+}
+
+impl ErrorCode {
+    /// The error a client reports for a response with this code and `message`
+    pub fn into_error(self, message: String) -> KvsError {
+        match self {
+            ErrorCode::KeyNotFound => KvsError::BadRemovalError,
+            ErrorCode::ReadOnly => KvsError::ReadOnlyError,
+            ErrorCode::MalformedRequest => KvsError::ProtocolError(message),
+            ErrorCode::Unknown | ErrorCode::Io | ErrorCode::Corruption | ErrorCode::Internal => {
+                KvsError::ServerError {
+                    code: self,
+                    message,
+                }
+            }
+        }
+    }
+}
+
+impl<'a> From<&'a KvsError> for ErrorCode {
+    fn from(err: &'a KvsError) -> ErrorCode {
+        match err {
+            KvsError::BadRemovalError => ErrorCode::KeyNotFound,
+            KvsError::ReadOnlyError => ErrorCode::ReadOnly,
+            KvsError::IoError(_) => ErrorCode::Io,
+            KvsError::CorruptionError(..) | KvsError::FormatVersionError(_) => {
+                ErrorCode::Corruption
+            }
+            KvsError::DecodeError(..) | KvsError::ProtocolError(_) => ErrorCode::MalformedRequest,
+            KvsError::ServerError { code, .. } => *code,
+            _ => ErrorCode::Internal,
+        }
+    }
+}
+
+impl From<u32> for ErrorCode {
+    fn from(code: u32) -> ErrorCode {
+        match code {
+            1 => ErrorCode::KeyNotFound,
+            2 => ErrorCode::ReadOnly,
+            3 => ErrorCode::Io,
+            4 => ErrorCode::Corruption,
+            5 => ErrorCode::MalformedRequest,
+            6 => ErrorCode::Internal,
+            _ => ErrorCode::Unknown,
+        }
+    }
+}
+
+impl From<ErrorCode> for u32 {
+    fn from(code: ErrorCode) -> u32 {
+        match code {
+            ErrorCode::Unknown => 0,
+            ErrorCode::KeyNotFound => 1,
+            ErrorCode::ReadOnly => 2,
+            ErrorCode::Io => 3,
+            ErrorCode::Corruption => 4,
+            ErrorCode::MalformedRequest => 5,
+            ErrorCode::Internal => 6,
+        }
+    }
+}
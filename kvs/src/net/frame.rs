@@ -0,0 +1,165 @@
+use crate::{KvsError, Result};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::io::{self, Read, Write};
+
+/// The largest message a frame may carry. Anything longer is treated as a corrupt stream rather
+/// than allocated.
+pub const MAX_FRAME_BYTES: u32 = 64 * 1024 * 1024;
+/// The size of the length that precedes every message
+const FRAME_HEADER_BYTES: usize = 4;
+
+/// Writes `message` as one frame: a little-endian `u32` length followed by the bincode-encoded
+/// message. The writer is not flushed.
+pub fn write_frame<W: Write, T: Serialize>(writer: &mut W, message: &T) -> Result<()> {
+    writer.write_all(&encode_frame(message)?)?;
+    Ok(())
+}
+
+/// Reads one frame and decodes the message it carries. Returns `None` if the stream ended cleanly
+/// before the frame began, which is how the other side closes a connection.
+///
+/// # Errors
+///
+/// - A `KvsError::DecodeError` will occur if the message cannot be decoded, if the stream ends
+///   partway through a frame, or if it announces a frame longer than `MAX_FRAME_BYTES`
+/// - A `KvsError::IoError` will occur if reading from the stream fails
+pub fn read_frame<R: Read, T: DeserializeOwned>(reader: &mut R) -> Result<Option<T>> {
+    match read_frame_bytes(reader)? {
+        Some(message) => decode_frame(&message).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Reads one frame without decoding it, returning the encoded message. Since the frame is always
+/// read whole, the stream is ready for the next frame even if the message turns out not to
+/// decode.
+///
+/// # Errors
+///
+/// See `read_frame`
+pub fn read_frame_bytes<R: Read>(reader: &mut R) -> Result<Option<Vec<u8>>> {
+    let mut header = [0; FRAME_HEADER_BYTES];
+    let filled = read_fully(reader, &mut header)?;
+    if filled == 0 {
+        return Ok(None);
+    }
+    if filled < header.len() {
+        return Err(truncated(filled));
+    }
+    let length = frame_length(header)?;
+    let mut message = Vec::with_capacity(length);
+    reader.take(length as u64).read_to_end(&mut message)?;
+    if message.len() < length {
+        return Err(truncated(FRAME_HEADER_BYTES + message.len()));
+    }
+    Ok(Some(message))
+}
+
+/// Decodes a message read by `read_frame_bytes`
+///
+/// # Errors
+///
+/// A `KvsError::DecodeError` will occur if the message does not decode as a `T`
+pub fn decode_frame<T: DeserializeOwned>(message: &[u8]) -> Result<T> {
+    bincode::deserialize(message)
+        .map_err(|err| KvsError::DecodeError(err.to_string(), message.len()))
+}
+
+/// Like `write_frame`, for tokio streams
+#[cfg(feature = "async")]
+pub async fn write_frame_async<W, T>(writer: &mut W, message: &T) -> Result<()>
+where
+    W: tokio::io::AsyncWrite + Unpin,
+    T: Serialize,
+{
+    use tokio::io::AsyncWriteExt;
+    writer.write_all(&encode_frame(message)?).await?;
+    Ok(())
+}
+
+/// Like `read_frame`, for tokio streams
+#[cfg(feature = "async")]
+pub async fn read_frame_async<R, T>(reader: &mut R) -> Result<Option<T>>
+where
+    R: tokio::io::AsyncRead + Unpin,
+    T: DeserializeOwned,
+{
+    match read_frame_bytes_async(reader).await? {
+        Some(message) => decode_frame(&message).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Like `read_frame_bytes`, for tokio streams
+#[cfg(feature = "async")]
+pub async fn read_frame_bytes_async<R>(reader: &mut R) -> Result<Option<Vec<u8>>>
+where
+    R: tokio::io::AsyncRead + Unpin,
+{
+    use tokio::io::AsyncReadExt;
+    let mut header = [0; FRAME_HEADER_BYTES];
+    let mut filled = 0;
+    while filled < header.len() {
+        match reader.read(&mut header[filled..]).await? {
+            0 if filled == 0 => return Ok(None),
+            0 => return Err(truncated(filled)),
+            read => filled += read,
+        }
+    }
+    let length = frame_length(header)?;
+    let mut message = Vec::with_capacity(length);
+    reader.take(length as u64).read_to_end(&mut message).await?;
+    if message.len() < length {
+        return Err(truncated(FRAME_HEADER_BYTES + message.len()));
+    }
+    Ok(Some(message))
+}
+
+fn encode_frame<T: Serialize>(message: &T) -> Result<Vec<u8>> {
+    let length = bincode::serialized_size(message)?;
+    if length > u64::from(MAX_FRAME_BYTES) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Message of {} bytes exceeds the frame limit", length),
+        )
+        .into());
+    }
+    let mut frame = Vec::with_capacity(FRAME_HEADER_BYTES + length as usize);
+    frame.extend_from_slice(&(length as u32).to_le_bytes());
+    bincode::serialize_into(&mut frame, message)?;
+    Ok(frame)
+}
+
+/// Reads until `buf` is full or the stream ends, returning how much was read
+fn read_fully<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err.into()),
+        }
+    }
+    Ok(filled)
+}
+
+fn frame_length(header: [u8; FRAME_HEADER_BYTES]) -> Result<usize> {
+    let length = u32::from_le_bytes(header);
+    if length > MAX_FRAME_BYTES {
+        return Err(KvsError::DecodeError(
+            "The frame exceeds the size limit".to_owned(),
+            length as usize,
+        ));
+    }
+    Ok(length as usize)
+}
+
+/// The error for a stream that ended after only `received` bytes of a frame
+fn truncated(received: usize) -> KvsError {
+    KvsError::DecodeError(
+        "The stream ended partway through a frame".to_owned(),
+        received,
+    )
+}
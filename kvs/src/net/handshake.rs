@@ -0,0 +1,206 @@
+use crate::{KvsError, Result};
+use serde::{Deserialize, Serialize};
+use std::ops::{BitAnd, BitOr};
+
+/// The newest version of the wire protocol this build speaks
+///
+/// The protocol evolves under these rules, so that deployed clients and servers keep working with
+/// newer ones:
+///
+/// - `Hello` and `HelloReply` never change, so two builds can always agree on whether they can
+///   talk to each other
+/// - variants are only ever appended to `KvsRequest` and `KvsResponse`, never reordered or removed,
+///   since bincode identifies variants by position
+/// - a new variant (or any other change a peer has to opt into) comes with a new capability or a
+///   new protocol version, and is never sent to a peer that did not negotiate it
+/// - dropping support for an old version means raising `MIN_PROTOCOL_VERSION`, which peers that
+///   only speak older versions find out about in the handshake
+///
+/// - 1: the first versioned protocol, with request ids and pipelining
+/// - 2: errors are answered with `KvsResponse::Failure`, which carries an `ErrorCode`, instead of
+///   `KvsResponse::Error`
+pub const PROTOCOL_VERSION: u32 = 2;
+/// The oldest version of the wire protocol this build still speaks
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+/// The first protocol version whose error responses carry an `ErrorCode`
+pub(crate) const ERROR_CODE_VERSION: u32 = 2;
+/// The magic number that begins every `Hello`, telling a kvs client apart from anything else that
+/// connects to the server
+const HELLO_MAGIC: [u8; 4] = *b"KVSP";
+
+/// A set of optional protocol features, some of which a peer may not support
+///
+/// Bits a peer does not know about are ignored, so new capabilities can be added without breaking
+/// older peers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Capabilities(u64);
+
+impl Capabilities {
+    /// The peer may send further requests before the response to an earlier one has arrived
+    pub const PIPELINING: Capabilities = Capabilities(1);
+    /// Responses to pipelined requests may arrive in a different order than the requests were
+    /// sent in
+    pub const UNORDERED_RESPONSES: Capabilities = Capabilities(1 << 1);
+
+    /// The set with no capabilities in it
+    pub fn empty() -> Capabilities {
+        Capabilities(0)
+    }
+
+    /// Whether every capability in `other` is also in this set
+    pub fn contains(self, other: Capabilities) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for Capabilities {
+    type Output = Capabilities;
+
+    fn bitor(self, other: Capabilities) -> Capabilities {
+        Capabilities(self.0 | other.0)
+    }
+}
+
+impl BitAnd for Capabilities {
+    type Output = Capabilities;
+
+    fn bitand(self, other: Capabilities) -> Capabilities {
+        Capabilities(self.0 & other.0)
+    }
+}
+
+/// The first frame a client sends on a new connection
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Hello {
+    magic: [u8; 4],
+    /// The oldest protocol version the client speaks
+    pub min_version: u32,
+    /// The newest protocol version the client speaks
+    pub max_version: u32,
+    /// What the client supports
+    pub capabilities: Capabilities,
+}
+
+/// The server's answer to a `Hello`, which is the first frame it sends on a new connection
+#[derive(Debug, Serialize, Deserialize)]
+pub enum HelloReply {
+    /// The connection is ready for requests
+    Accepted {
+        /// The protocol version used from here on, the newest one both sides speak
+        version: u32,
+        /// The capabilities both sides support
+        capabilities: Capabilities,
+    },
+    /// The server cannot talk to the client, and closes the connection
+    Rejected {
+        /// The oldest protocol version the server speaks
+        min_version: u32,
+        /// The newest protocol version the server speaks
+        max_version: u32,
+        /// Why the connection was rejected
+        message: String,
+    },
+}
+
+/// What both sides of a connection agreed on in the handshake
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Session {
+    /// The protocol version in use
+    pub version: u32,
+    /// The capabilities both sides support
+    pub capabilities: Capabilities,
+}
+
+impl Hello {
+    /// Creates the `Hello` of a client built from this crate
+    pub fn new(capabilities: Capabilities) -> Hello {
+        Hello {
+            magic: HELLO_MAGIC,
+            min_version: MIN_PROTOCOL_VERSION,
+            max_version: PROTOCOL_VERSION,
+            capabilities,
+        }
+    }
+
+    /// Decides how the server answers this `Hello`, given what the server supports
+    pub fn accept(&self, capabilities: Capabilities) -> HelloReply {
+        if self.magic != HELLO_MAGIC {
+            return HelloReply::rejected("Not a kvs client".to_owned());
+        }
+        let version = self.max_version.min(PROTOCOL_VERSION);
+        if version < self.min_version.max(MIN_PROTOCOL_VERSION) {
+            return HelloReply::rejected(version_mismatch(
+                self.min_version,
+                self.max_version,
+                MIN_PROTOCOL_VERSION,
+                PROTOCOL_VERSION,
+            ));
+        }
+        HelloReply::Accepted {
+            version,
+            capabilities: self.capabilities & capabilities,
+        }
+    }
+}
+
+impl HelloReply {
+    /// The reply to a client that did not open with a valid `Hello` at all
+    pub fn rejected(message: String) -> HelloReply {
+        HelloReply::Rejected {
+            min_version: MIN_PROTOCOL_VERSION,
+            max_version: PROTOCOL_VERSION,
+            message,
+        }
+    }
+
+    /// Turns the reply into the session the client goes on with
+    ///
+    /// # Errors
+    ///
+    /// - A `KvsError::ProtocolVersionError` will occur if the server speaks none of the protocol
+    ///   versions this build does
+    /// - A `KvsError::HandshakeError` will occur if the server rejected the connection for any
+    ///   other reason, or accepted it with a version this build does not speak
+    pub fn into_session(self) -> Result<Session> {
+        match self {
+            HelloReply::Accepted {
+                version,
+                capabilities,
+            } => {
+                if !(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&version) {
+                    return Err(KvsError::HandshakeError(format!(
+                        "The server chose protocol version {}",
+                        version
+                    )));
+                }
+                Ok(Session {
+                    version,
+                    capabilities,
+                })
+            }
+            HelloReply::Rejected {
+                min_version,
+                max_version,
+                message,
+            } => {
+                if max_version < MIN_PROTOCOL_VERSION || min_version > PROTOCOL_VERSION {
+                    Err(KvsError::ProtocolVersionError(version_mismatch(
+                        MIN_PROTOCOL_VERSION,
+                        PROTOCOL_VERSION,
+                        min_version,
+                        max_version,
+                    )))
+                } else {
+                    Err(KvsError::HandshakeError(message))
+                }
+            }
+        }
+    }
+}
+
+fn version_mismatch(client_min: u32, client_max: u32, server_min: u32, server_max: u32) -> String {
+    format!(
+        "the client speaks versions {} to {}, the server {} to {}",
+        client_min, client_max, server_min, server_max
+    )
+}
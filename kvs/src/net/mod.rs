@@ -1,3 +1,5 @@
+use crate::{KvsError, Result};
+use handshake::ERROR_CODE_VERSION;
 use serde::{Deserialize, Serialize};
 
 /// A serializiable representation of a KvsEngine command
@@ -34,15 +36,146 @@ pub enum KvsResponse {
     Set,
     /// Representation of a successful Remove
     Remove,
-    /// Representation of some error
+    /// Representation of some error, as sent to peers speaking protocol version 1
     Error {
         /// The associated error message
         message: String,
     },
+    /// Representation of some error, as sent to peers speaking protocol version 2 or newer
+    Failure {
+        /// What kind of error occured
+        code: ErrorCode,
+        /// The associated error message
+        message: String,
+    },
+}
+
+impl KvsResponse {
+    /// The response reporting `err`
+    pub fn error(err: &KvsError) -> KvsResponse {
+        KvsResponse::Failure {
+            code: ErrorCode::from(err),
+            message: err.to_string(),
+        }
+    }
+
+    /// The response as a peer speaking protocol `version` understands it
+    pub(crate) fn for_version(self, version: u32) -> KvsResponse {
+        match self {
+            KvsResponse::Failure { message, .. } if version < ERROR_CODE_VERSION => {
+                KvsResponse::Error { message }
+            }
+            response => response,
+        }
+    }
+
+    /// Turns an error response into the `KvsError` it stands for, passing any other response
+    /// through
+    ///
+    /// # Errors
+    ///
+    /// The error described by a `KvsResponse::Failure`, as mapped by `ErrorCode::into_error`, or
+    /// a `KvsError::ServerError` for a `KvsResponse::Error` from a version 1 peer
+    pub fn into_result(self) -> Result<KvsResponse> {
+        match self {
+            KvsResponse::Failure { code, message } => Err(code.into_error(message)),
+            KvsResponse::Error { message } => Err(ErrorCode::Unknown.into_error(message)),
+            response => Ok(response),
+        }
+    }
+}
+
+/// A request as it travels over a connection, tagged with an id chosen by the client
+///
+/// A client may send any number of requests before reading the responses. The server answers
+/// each one with a `ResponseEnvelope` carrying the same id, which is how the client matches
+/// responses to requests when they come back in a different order.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RequestEnvelope {
+    /// Identifies the request among those in flight on the connection
+    pub id: u64,
+    /// The request itself
+    pub request: KvsRequest,
+}
+
+impl RequestEnvelope {
+    /// Reads the id of an encoded request, even one that does not decode as a whole (such as a
+    /// request using a variant the server does not know)
+    pub(crate) fn peek_id(message: &[u8]) -> u64 {
+        // bincode encodes the id first, as a little-endian u64
+        let mut id = [0; 8];
+        match message.get(..id.len()) {
+            Some(bytes) => {
+                id.copy_from_slice(bytes);
+                u64::from_le_bytes(id)
+            }
+            None => UNKNOWN_REQUEST_ID,
+        }
+    }
+}
+
+/// The id a server answers with when a request is too damaged to read its id from
+pub const UNKNOWN_REQUEST_ID: u64 = u64::MAX;
+
+/// A response as it travels over a connection, tagged with the id of the request it answers
+///
+/// A request the server cannot decode is answered with an error response, carrying the id of the
+/// request if it could be read and `UNKNOWN_REQUEST_ID` otherwise.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ResponseEnvelope {
+    /// The id of the request being answered
+    pub id: u64,
+    /// The response itself
+    pub response: KvsResponse,
+}
+
+impl ResponseEnvelope {
+    /// The envelope as a peer speaking protocol `version` understands it
+    pub(crate) fn for_version(self, version: u32) -> ResponseEnvelope {
+        ResponseEnvelope {
+            id: self.id,
+            response: self.response.for_version(version),
+        }
+    }
+
+    /// The error for a response that answers none of the requests the client is waiting on
+    pub(crate) fn into_unexpected(self) -> KvsError {
+        match self.response {
+            KvsResponse::Error { message } | KvsResponse::Failure { message, .. }
+                if self.id == UNKNOWN_REQUEST_ID =>
+            {
+                KvsError::ProtocolError(format!("The server could not read a request: {}", message))
+            }
+            _ => KvsError::ProtocolError(format!("Unexpected response id {}", self.id)),
+        }
+    }
 }
 
+#[cfg(feature = "async")]
+pub use async_client::AsyncKvsClient;
+#[cfg(feature = "async")]
+pub use async_server::AsyncKvsServer;
+pub use addr::KvsAddr;
 pub use client::KvsClient;
+pub use error_code::ErrorCode;
+pub use handshake::{
+    Capabilities, Hello, HelloReply, Session, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
+};
+pub use options::KvsClientOptions;
 pub use server::KvsServer;
+pub use shutdown::ShutdownHandle;
 
+#[cfg(feature = "async")]
+mod async_client;
+#[cfg(feature = "async")]
+mod async_server;
+mod addr;
 mod client;
+mod error_code;
+mod frame;
+mod handshake;
+mod options;
+mod pool;
 mod server;
+mod shutdown;
+mod transport;
@@ -0,0 +1,128 @@
+use super::addr::KvsAddr;
+use super::client::KvsClient;
+use std::time::Duration;
+
+/// How long connecting to the server may take, by default
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long a single read from or write to the server may block, by default
+const DEFAULT_IO_TIMEOUT: Duration = Duration::from_secs(30);
+/// How many idle connections a client keeps open, by default
+const DEFAULT_MAX_IDLE_CONNECTIONS: usize = 8;
+/// How many times a failed `get` is retried, by default
+const DEFAULT_RETRIES: u32 = 2;
+/// How long to wait before the first retry, by default
+const DEFAULT_BACKOFF: Duration = Duration::from_millis(50);
+/// The longest wait between two retries, by default
+const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Options used to create a `KvsClient`, in the style of `KvStoreOptions`
+///
+/// # Example
+///
+/// ```
+/// use kvs::KvsClientOptions;
+/// use std::time::Duration;
+/// let client = KvsClientOptions::new()
+///     .read_timeout(Some(Duration::from_secs(2)))
+///     .retries(5)
+///     .build("127.0.0.1:4000".parse().unwrap());
+/// ```
+#[derive(Clone, Debug)]
+pub struct KvsClientOptions {
+    pub(super) connect_timeout: Option<Duration>,
+    pub(super) read_timeout: Option<Duration>,
+    pub(super) write_timeout: Option<Duration>,
+    pub(super) max_idle_connections: usize,
+    pub(super) retries: u32,
+    pub(super) backoff: Duration,
+    pub(super) max_backoff: Duration,
+    pub(super) unordered_responses: bool,
+}
+
+impl KvsClientOptions {
+    /// Creates the default options: a 5 second connect timeout, 30 second read and write
+    /// timeouts, up to 8 idle connections, 2 retries of a failed `get` starting 50 ms apart, and
+    /// responses in the order their requests were sent
+    pub fn new() -> KvsClientOptions {
+        KvsClientOptions {
+            connect_timeout: Some(DEFAULT_CONNECT_TIMEOUT),
+            read_timeout: Some(DEFAULT_IO_TIMEOUT),
+            write_timeout: Some(DEFAULT_IO_TIMEOUT),
+            max_idle_connections: DEFAULT_MAX_IDLE_CONNECTIONS,
+            retries: DEFAULT_RETRIES,
+            backoff: DEFAULT_BACKOFF,
+            max_backoff: DEFAULT_MAX_BACKOFF,
+            unordered_responses: false,
+        }
+    }
+
+    /// Sets how long connecting to the server may take, or `None` to wait as long as the
+    /// operating system does. The timeout must not be zero.
+    ///
+    /// Connecting to a Unix domain socket does not wait on the network, so it has no timeout.
+    pub fn connect_timeout(&mut self, timeout: Option<Duration>) -> &mut KvsClientOptions {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Sets how long a single read from the server may block, or `None` to block forever. The
+    /// timeout must not be zero.
+    pub fn read_timeout(&mut self, timeout: Option<Duration>) -> &mut KvsClientOptions {
+        self.read_timeout = timeout;
+        self
+    }
+
+    /// Sets how long a single write to the server may block, or `None` to block forever. The
+    /// timeout must not be zero.
+    pub fn write_timeout(&mut self, timeout: Option<Duration>) -> &mut KvsClientOptions {
+        self.write_timeout = timeout;
+        self
+    }
+
+    /// Sets how many connections are kept open once their requests are done, ready for the
+    /// requests that follow. More connections than this may be open while requests are in
+    /// flight.
+    pub fn max_idle_connections(&mut self, connections: usize) -> &mut KvsClientOptions {
+        self.max_idle_connections = connections;
+        self
+    }
+
+    /// Sets how many times a `get` that failed to reach the server (or to hear back from it) is
+    /// retried. Changes are never retried, since the server may have carried them out already.
+    pub fn retries(&mut self, retries: u32) -> &mut KvsClientOptions {
+        self.retries = retries;
+        self
+    }
+
+    /// Sets how long to wait before the first retry. The wait doubles with every retry after
+    /// that, up to `max_backoff`.
+    pub fn backoff(&mut self, backoff: Duration, max_backoff: Duration) -> &mut KvsClientOptions {
+        self.backoff = backoff;
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    /// Sets whether the server may answer pipelined requests out of order, as soon as each is
+    /// done, if it supports that. Without this, a slow request holds up the responses to every
+    /// request sent after it on the same connection.
+    ///
+    /// The server may then carry out pipelined requests concurrently, so the order they take
+    /// effect in is not guaranteed either. `AsyncKvsServer` only overlaps `get`s, which keeps
+    /// them in order, but other servers need not. `KvsServer` always answers in order.
+    pub fn unordered_responses(&mut self, unordered: bool) -> &mut KvsClientOptions {
+        self.unordered_responses = unordered;
+        self
+    }
+
+    /// Creates a client of the server at `addr` with these options. No connection is opened
+    /// until the first request.
+    pub fn build(&self, addr: KvsAddr) -> KvsClient {
+        KvsClient::with_options(addr, self.clone())
+    }
+}
+
+impl Default for KvsClientOptions {
+    fn default() -> KvsClientOptions {
+        KvsClientOptions::new()
+    }
+}
@@ -0,0 +1,39 @@
+use super::client::Connection;
+use std::sync::Mutex;
+
+/// The idle connections of a `KvsClient` and its clones, waiting for their next request
+pub struct Pool {
+    idle: Mutex<Vec<Connection>>,
+    max_idle: usize,
+}
+
+impl Pool {
+    /// Creates an empty pool that keeps at most `max_idle` connections
+    pub fn new(max_idle: usize) -> Pool {
+        Pool {
+            idle: Mutex::new(Vec::new()),
+            max_idle,
+        }
+    }
+
+    /// Takes the most recently used idle connection that is still healthy, dropping any dead
+    /// connections found along the way
+    pub fn take(&self) -> Option<Connection> {
+        let mut idle = self.idle.lock().unwrap();
+        while let Some(connection) = idle.pop() {
+            if connection.is_healthy() {
+                return Some(connection);
+            }
+            debug!("Dropping a dead pooled connection");
+        }
+        None
+    }
+
+    /// Returns a connection whose requests are done, dropping it if the pool is full
+    pub fn put(&self, connection: Connection) {
+        let mut idle = self.idle.lock().unwrap();
+        if idle.len() < self.max_idle {
+            idle.push(connection);
+        }
+    }
+}
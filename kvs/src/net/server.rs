@@ -1,97 +1,254 @@
 extern crate bincode;
 
-use crate::{KvsRequest, KvsResponse, KvsEngine, Result};
-use std::io::{Read, Write};
-use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use super::addr::KvsAddr;
+use super::frame::{decode_frame, read_frame, read_frame_bytes, write_frame};
+use super::shutdown::ShutdownHandle;
+use super::transport::{Listener, Stream};
+use crate::{
+    Capabilities, Hello, HelloReply, KvsError, KvsRequest, KvsResponse, KvsEngine,
+    RequestEnvelope, ResponseEnvelope, Result, ThreadPool, UNKNOWN_REQUEST_ID,
+};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::net::Shutdown;
+use std::time::Duration;
+
+/// How long a connection may wait for its next request before it is closed, by default
+const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(5);
 
 /// A server for hosting a key value store
-pub struct KvsServer<E: KvsEngine> {
-    addr: SocketAddr,
+pub struct KvsServer<E: KvsEngine, P: ThreadPool> {
+    addr: KvsAddr,
+    /// The listener bound by `KvsServer::bind`, if the server was created that way
+    listener: Option<Listener>,
     engine: E,
+    pool: P,
+    shutdown: ShutdownHandle,
+    idle_timeout: Option<Duration>,
 }
 
-impl<E: KvsEngine> KvsServer<E> {
-    /// Creates a new server ready to accept key-value requests
+impl<E: KvsEngine, P: ThreadPool> KvsServer<E, P> {
+    /// Creates a new server ready to accept key-value requests. The address is only bound once
+    /// the server starts serving.
     ///
     /// # Arguments
     ///
-    /// - addr - the address to bind to
+    /// - addr - the address to bind to, a TCP address or a Unix domain socket
     /// - engine - the engine to use for storage
-    pub fn new(addr: SocketAddr, engine: E) -> KvsServer<E> {
-        KvsServer { addr, engine }
+    /// - pool - the threads that connections are handled on
+    pub fn new(addr: KvsAddr, engine: E, pool: P) -> KvsServer<E, P> {
+        KvsServer {
+            addr,
+            listener: None,
+            engine,
+            pool,
+            shutdown: ShutdownHandle::default(),
+            idle_timeout: Some(DEFAULT_IDLE_TIMEOUT),
+        }
     }
 
-    /// Waits for incoming connections indefinitely (until killed)
+    /// Creates a new server and binds its address straight away, so that `local_addr` reports
+    /// the port actually assigned when binding to port 0. Clients may connect as soon as this
+    /// returns; their connections wait until the server starts serving.
+    ///
+    /// # Arguments
+    ///
+    /// See `KvsServer::new`
     ///
     /// # Errors
     ///
     /// An error may occur if there is a problem binding to the bind address
-    pub fn serve(&mut self) -> Result<()> {
-        let listener = TcpListener::bind(self.addr)?;
-        for stream in listener.incoming() {
-            match stream {
-                Ok(mut stream) => {
-                    info!("New connection from {}", stream.peer_addr().unwrap().ip());
-                    self.handle_request(&mut stream);
-                    match stream.shutdown(Shutdown::Write) {
-                        Ok(_) => {}
+    pub fn bind(addr: KvsAddr, engine: E, pool: P) -> Result<KvsServer<E, P>> {
+        let listener = Listener::bind(&addr)?;
+        let mut server = KvsServer::new(listener.local_addr()?, engine, pool);
+        server.listener = Some(listener);
+        Ok(server)
+    }
+
+    /// The address the server listens on. For a server created with `KvsServer::new`, this is
+    /// the address it was given, since nothing is bound until it starts serving.
+    pub fn local_addr(&self) -> KvsAddr {
+        self.addr.clone()
+    }
+
+    /// Sets how long a connection may wait for its next request before the server closes it (5
+    /// seconds by default), or `None` to keep idle connections open until their clients close
+    /// them. The timeout must not be zero.
+    ///
+    /// Every open connection keeps a thread of the pool busy, so without this a few clients that
+    /// keep idle connections around are enough to lock every other client out.
+    /// `KvsClient` notices when an idle connection of its own was closed and opens a new one.
+    pub fn idle_timeout(&mut self, timeout: Option<Duration>) -> &mut KvsServer<E, P> {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// Returns a handle that shuts the server down
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Waits for incoming connections until the server is shut down through a `ShutdownHandle`.
+    /// Each connection is handed to the thread pool along with its own handle to the engine, and
+    /// keeps a thread of the pool busy until the client closes it or it has been idle for longer
+    /// than the idle timeout.
+    ///
+    /// Once shut down, the server waits for the requests in flight (see `ShutdownHandle`) and
+    /// flushes the engine to disk before returning.
+    ///
+    /// # Errors
+    ///
+    /// - An error may occur if there is a problem binding to the bind address
+    /// - An error may occur if flushing the engine fails
+    pub fn serve(&self) -> Result<()> {
+        let bound;
+        let listener = match &self.listener {
+            Some(listener) => listener,
+            None => {
+                bound = Listener::bind(&self.addr)?;
+                &bound
+            }
+        };
+        if self.shutdown.listening(listener.local_addr()?) {
+            self.accept(listener);
+        }
+        self.shutdown.drain();
+        info!("Flushing the engine");
+        self.engine.flush()
+    }
+
+    /// Hands connections to the thread pool until shutdown is asked for
+    fn accept(&self, listener: &Listener) {
+        loop {
+            match listener.accept() {
+                Ok(stream) => {
+                    let guard = match self.shutdown.register(&stream) {
+                        Ok(Some(guard)) => guard,
+                        Ok(None) => break,
                         Err(err) => {
-                            warn!("Failed to close socket: {}", err);
+                            // dropping the stream closes the connection
+                            warn!("Failed to track connection from {}: {}", stream.peer(), err);
+                            continue;
+                        }
+                    };
+                    info!("New connection from {}", stream.peer());
+                    let engine = self.engine.clone();
+                    let idle_timeout = self.idle_timeout;
+                    self.pool.spawn(move || {
+                        let _guard = guard;
+                        if let Err(err) = handle_connection(&engine, &stream, idle_timeout) {
+                            error!("Failed while serving connection: {}", err);
                         }
-                    }
+                        match stream.shutdown(Shutdown::Both) {
+                            Ok(_) => {}
+                            Err(err) => {
+                                warn!("Failed to close socket: {}", err);
+                            }
+                        }
+                    });
                 }
                 Err(err) => {
                     warn!("Failed while accepting stream: {}", err);
                 }
             };
         }
-        Ok(())
     }
+}
 
-    fn handle_request(&mut self, stream: &mut TcpStream) {
-        let mut request_buf = Vec::new();
-        if let Err(err) = stream.read_to_end(&mut request_buf) {
-            error!("Failed while reading request: {}", err);
-            return;
-        };
+/// Answers the handshake on a connection and then its requests in the order they arrive, until
+/// the client closes it or sends nothing for `idle_timeout`. Responses are only flushed once every
+/// request the client has sent so far is answered, so a pipelined batch of requests goes back out
+/// in as few writes as possible.
+///
+/// A request that does not decode is answered with an error and the connection carries on, but a
+/// frame that is cut short (or too long to read) ends the connection after the error is sent.
+fn handle_connection<E: KvsEngine>(
+    engine: &E,
+    stream: &Stream,
+    idle_timeout: Option<Duration>,
+) -> Result<()> {
+    stream.set_timeouts(idle_timeout, None)?;
+    let mut reader = BufReader::new(stream);
+    let mut writer = BufWriter::new(stream);
+    let reply = match read_frame::<_, Hello>(&mut reader) {
+        Ok(Some(hello)) => hello.accept(Capabilities::PIPELINING),
+        Ok(None) => return Ok(()),
+        Err(err @ KvsError::DecodeError(..)) => {
+            HelloReply::rejected(format!("Expected a handshake: {}", err))
+        }
+        Err(ref err) if timed_out(err) => return Ok(()),
+        Err(err) => return Err(err),
+    };
+    write_frame(&mut writer, &reply)?;
+    writer.flush()?;
+    let version = match reply {
+        HelloReply::Accepted { version, .. } => version,
+        HelloReply::Rejected { message, .. } => {
+            warn!("Rejected connection: {}", message);
+            return Ok(());
+        }
+    };
 
-        let request = match bincode::deserialize(&request_buf) {
-            Ok(cmd) => cmd,
-            Err(err) => {
-                error!("Failed while deserializing request: {}", err);
-                return;
-            }
-        };
-        let response = match request {
-            KvsRequest::Get { key } => match self.engine.get(key) {
-                Ok(value) => KvsResponse::Get { value },
-                Err(err) => KvsResponse::Error {
-                    message: err.to_string(),
-                },
-            },
-            KvsRequest::Remove { key } => match self.engine.remove(key) {
-                Ok(_) => KvsResponse::Remove {},
-                Err(err) => KvsResponse::Error {
-                    message: err.to_string(),
+    loop {
+        let envelope = match read_frame_bytes(&mut reader) {
+            Ok(Some(message)) => match decode_frame(&message) {
+                Ok(RequestEnvelope { id, request }) => ResponseEnvelope {
+                    id,
+                    response: handle_request(engine, request),
                 },
+                Err(err) => malformed_request(RequestEnvelope::peek_id(&message), &err),
             },
-            KvsRequest::Set { key, value } => match self.engine.set(key, value) {
-                Ok(_) => KvsResponse::Set {},
-                Err(err) => KvsResponse::Error {
-                    message: err.to_string(),
-                },
-            },
-        };
-
-        let serialized = match bincode::serialize(&response) {
-            Ok(value) => value,
-            Err(err) => {
-                error!("Failed while serializing request: {}", err);
-                return;
+            Ok(None) => return Ok(()),
+            Err(err @ KvsError::DecodeError(..)) => {
+                let envelope = malformed_request(UNKNOWN_REQUEST_ID, &err);
+                write_frame(&mut writer, &envelope.for_version(version))?;
+                writer.flush()?;
+                return Ok(());
+            }
+            Err(ref err) if timed_out(err) => {
+                info!("Closing connection idle for longer than {:?}", idle_timeout.unwrap());
+                return Ok(());
             }
+            Err(err) => return Err(err),
         };
-        if let Err(err) = stream.write_all(&serialized) {
-            error!("Failed while writing response: {}", err);
+        write_frame(&mut writer, &envelope.for_version(version))?;
+        if reader.buffer().is_empty() {
+            writer.flush()?;
+        }
+    }
+}
+
+/// Whether reading failed because the connection's read timeout ran out
+fn timed_out(err: &KvsError) -> bool {
+    match err {
+        KvsError::IoError(err) => {
+            matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut)
         }
+        _ => false,
+    }
+}
+
+/// The answer to a request that could not be decoded
+pub(super) fn malformed_request(id: u64, err: &KvsError) -> ResponseEnvelope {
+    warn!("Received a malformed request: {}", err);
+    ResponseEnvelope {
+        id,
+        response: KvsResponse::error(err),
+    }
+}
+
+fn handle_request<E: KvsEngine>(engine: &E, request: KvsRequest) -> KvsResponse {
+    match request {
+        KvsRequest::Get { key } => match engine.get(key) {
+            Ok(value) => KvsResponse::Get { value },
+            Err(err) => KvsResponse::error(&err),
+        },
+        KvsRequest::Remove { key } => match engine.remove(key) {
+            Ok(_) => KvsResponse::Remove {},
+            Err(err) => KvsResponse::error(&err),
+        },
+        KvsRequest::Set { key, value } => match engine.set(key, value) {
+            Ok(_) => KvsResponse::Set {},
+            Err(err) => KvsResponse::error(&err),
+        },
     }
 }
@@ -0,0 +1,191 @@
+use super::addr::KvsAddr;
+use super::transport::{connectable, Stream};
+use std::collections;
+use std::io;
+use std::net::Shutdown;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+#[cfg(feature = "async")]
+use tokio::sync::watch;
+
+/// Stops a running `KvsServer` or `AsyncKvsServer` from another thread (or a signal handler's
+/// thread)
+///
+/// Shutting down makes the server stop accepting connections and stop reading requests from the
+/// connections it has open. Requests that were already being carried out get to finish and send
+/// their responses, for up to the grace period; connections still open after that are closed
+/// regardless. The server then flushes its engine to disk and `KvsServer::serve` returns (or the
+/// future returned by `AsyncKvsServer::serve` completes).
+///
+/// # Example
+///
+/// ```no_run
+/// use kvs::{KvStore, KvsServer, SharedQueueThreadPool, ThreadPool};
+/// use std::time::Duration;
+/// let engine = KvStore::open(std::path::Path::new("/var/db/"))?;
+/// let server = KvsServer::new(
+///     "127.0.0.1:4000".parse().unwrap(),
+///     engine,
+///     SharedQueueThreadPool::new(4)?,
+/// );
+/// let handle = server.shutdown_handle();
+/// std::thread::spawn(move || handle.shutdown(Duration::from_secs(5)));
+/// server.serve()?;
+/// # Ok::<(), kvs::KvsError>(())
+/// ```
+#[derive(Clone, Default)]
+pub struct ShutdownHandle {
+    state: Arc<ShutdownState>,
+}
+
+struct ShutdownState {
+    inner: Mutex<Inner>,
+    /// Signalled whenever a connection closes
+    closed: Condvar,
+    /// Set once shutdown has been asked for, for `AsyncKvsServer` to wait on. The receiver is
+    /// never read, so every receiver cloned from it sees the change.
+    #[cfg(feature = "async")]
+    stopping: (watch::Sender<bool>, watch::Receiver<bool>),
+}
+
+impl Default for ShutdownState {
+    fn default() -> ShutdownState {
+        ShutdownState {
+            inner: Mutex::default(),
+            closed: Condvar::new(),
+            #[cfg(feature = "async")]
+            stopping: watch::channel(false),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Inner {
+    /// When open connections are closed regardless, once shutdown has been asked for
+    deadline: Option<Instant>,
+    /// The address the server is listening on, while it is serving
+    addr: Option<KvsAddr>,
+    /// Every connection being served, to stop reading from when shutting down
+    connections: collections::HashMap<u64, Stream>,
+    next_connection: u64,
+}
+
+/// Unregisters a connection from its `ShutdownHandle` when dropped, even if serving the
+/// connection panicked
+pub(super) struct ConnectionGuard {
+    handle: ShutdownHandle,
+    id: u64,
+}
+
+impl ShutdownHandle {
+    /// Shuts the server down, giving requests already being carried out `grace` to finish. Does
+    /// not wait for the server to stop; `KvsServer::serve` returns once it has. Shutting down a
+    /// server that is not serving yet makes it stop as soon as it starts.
+    pub fn shutdown(&self, grace: Duration) {
+        let mut inner = self.state.inner.lock().unwrap();
+        if inner.deadline.is_some() {
+            return;
+        }
+        info!("Shutting down");
+        inner.deadline = Some(Instant::now() + grace);
+        for stream in inner.connections.values() {
+            // wakes up a handler waiting for the next request, but lets it answer one it has read
+            let _ = stream.shutdown(Shutdown::Read);
+        }
+        self.state.closed.notify_all();
+        #[cfg(feature = "async")]
+        {
+            // cannot fail, since the state keeps a receiver
+            let _ = self.state.stopping.0.send(true);
+        }
+        let addr = inner.addr.clone();
+        drop(inner);
+        if let Some(addr) = addr {
+            // wakes up the server waiting for the next connection
+            let _ = Stream::connect(&connectable(&addr), None);
+        }
+    }
+
+    /// Whether shutdown has been asked for
+    pub fn is_shutdown(&self) -> bool {
+        self.state.inner.lock().unwrap().deadline.is_some()
+    }
+
+    /// When connections still open are closed regardless, if shutdown has been asked for
+    #[cfg(feature = "async")]
+    pub(super) fn deadline(&self) -> Option<Instant> {
+        self.state.inner.lock().unwrap().deadline
+    }
+
+    /// Returns a receiver whose `changed` resolves once shutdown has been asked for, straight
+    /// away if it already has been
+    #[cfg(feature = "async")]
+    pub(super) fn stopping(&self) -> watch::Receiver<bool> {
+        self.state.stopping.1.clone()
+    }
+
+    /// Records the address the server is listening on. Returns false if shutdown has already
+    /// been asked for, in which case the server should not accept any connections.
+    pub(super) fn listening(&self, addr: KvsAddr) -> bool {
+        let mut inner = self.state.inner.lock().unwrap();
+        inner.addr = Some(addr);
+        inner.deadline.is_none()
+    }
+
+    /// Registers a newly accepted connection. Returns `None` if shutdown has been asked for, in
+    /// which case the server should stop accepting connections.
+    ///
+    /// # Errors
+    ///
+    /// An `io::Error` will occur if the connection cannot be tracked (when the process has run
+    /// out of file descriptors, for instance), in which case it should not be served
+    pub(super) fn register(&self, stream: &Stream) -> io::Result<Option<ConnectionGuard>> {
+        let mut inner = self.state.inner.lock().unwrap();
+        if inner.deadline.is_some() {
+            return Ok(None);
+        }
+        let stream = stream.try_clone()?;
+        let id = inner.next_connection;
+        inner.next_connection += 1;
+        inner.connections.insert(id, stream);
+        Ok(Some(ConnectionGuard {
+            handle: self.clone(),
+            id,
+        }))
+    }
+
+    /// Waits until every registered connection has closed or the grace period has run out,
+    /// closing any connections left after that
+    pub(super) fn drain(&self) {
+        let mut inner = self.state.inner.lock().unwrap();
+        while !inner.connections.is_empty() {
+            let now = Instant::now();
+            let deadline = inner.deadline.unwrap_or(now);
+            if deadline <= now {
+                warn!(
+                    "Closing {} connections that outlived the shutdown grace period",
+                    inner.connections.len()
+                );
+                for stream in inner.connections.values() {
+                    let _ = stream.shutdown(Shutdown::Both);
+                }
+                break;
+            }
+            inner = self
+                .state
+                .closed
+                .wait_timeout(inner, deadline - now)
+                .unwrap()
+                .0;
+        }
+        inner.addr = None;
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let mut inner = self.handle.state.inner.lock().unwrap();
+        inner.connections.remove(&self.id);
+        self.handle.state.closed.notify_all();
+    }
+}
@@ -0,0 +1,237 @@
+use super::addr::KvsAddr;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::time::Duration;
+
+/// A connection between a client and a server, over whichever transport its `KvsAddr` names
+pub enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+/// A socket a server accepts connections on
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixSocket),
+}
+
+/// A bound Unix domain socket, whose file is removed once the socket is closed
+#[cfg(unix)]
+pub struct UnixSocket {
+    listener: UnixListener,
+    path: std::path::PathBuf,
+}
+
+impl Stream {
+    /// Connects to `addr`, giving up after `timeout` if there is one. Connecting to a Unix
+    /// domain socket never waits on the network, so it ignores the timeout.
+    pub fn connect(addr: &KvsAddr, timeout: Option<Duration>) -> io::Result<Stream> {
+        match addr {
+            KvsAddr::Tcp(addr) => {
+                let stream = match timeout {
+                    Some(timeout) => TcpStream::connect_timeout(addr, timeout)?,
+                    None => TcpStream::connect(addr)?,
+                };
+                stream.set_nodelay(true)?;
+                Ok(Stream::Tcp(stream))
+            }
+            #[cfg(unix)]
+            KvsAddr::Unix(path) => Ok(Stream::Unix(UnixStream::connect(path)?)),
+        }
+    }
+
+    pub fn try_clone(&self) -> io::Result<Stream> {
+        match self {
+            Stream::Tcp(stream) => stream.try_clone().map(Stream::Tcp),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.try_clone().map(Stream::Unix),
+        }
+    }
+
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.shutdown(how),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.shutdown(how),
+        }
+    }
+
+    pub fn set_timeouts(&self, read: Option<Duration>, write: Option<Duration>) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => {
+                stream.set_read_timeout(read)?;
+                stream.set_write_timeout(write)
+            }
+            #[cfg(unix)]
+            Stream::Unix(stream) => {
+                stream.set_read_timeout(read)?;
+                stream.set_write_timeout(write)
+            }
+        }
+    }
+
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.set_nonblocking(nonblocking),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.set_nonblocking(nonblocking),
+        }
+    }
+
+    /// Describes the other end of the connection, for logging
+    pub fn peer(&self) -> String {
+        match self {
+            Stream::Tcp(stream) => match stream.peer_addr() {
+                Ok(addr) => addr.ip().to_string(),
+                Err(_) => "an unknown address".to_owned(),
+            },
+            #[cfg(unix)]
+            Stream::Unix(_) => "a Unix domain socket".to_owned(),
+        }
+    }
+}
+
+impl Read for &Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => (&*stream).read(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => (&*stream).read(buf),
+        }
+    }
+}
+
+impl Write for &Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => (&*stream).write(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => (&*stream).write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => (&*stream).flush(),
+            #[cfg(unix)]
+            Stream::Unix(stream) => (&*stream).flush(),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        (&*self).read(buf)
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        (&*self).write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (&*self).flush()
+    }
+}
+
+impl Listener {
+    /// Binds `addr`. A Unix domain socket file left behind by a server that is no longer running
+    /// is replaced, but one a server is still listening on is not, and neither is anything at the
+    /// path that is not a socket.
+    pub fn bind(addr: &KvsAddr) -> io::Result<Listener> {
+        match addr {
+            KvsAddr::Tcp(addr) => TcpListener::bind(addr).map(Listener::Tcp),
+            #[cfg(unix)]
+            KvsAddr::Unix(path) => {
+                remove_stale_socket(path)?;
+                let listener = UnixListener::bind(path)?;
+                Ok(Listener::Unix(UnixSocket {
+                    listener,
+                    path: path.clone(),
+                }))
+            }
+        }
+    }
+
+    /// The address the listener is bound to, with the port actually assigned for TCP
+    pub fn local_addr(&self) -> io::Result<KvsAddr> {
+        match self {
+            Listener::Tcp(listener) => listener.local_addr().map(KvsAddr::Tcp),
+            #[cfg(unix)]
+            Listener::Unix(socket) => Ok(KvsAddr::Unix(socket.path.clone())),
+        }
+    }
+
+    pub fn accept(&self) -> io::Result<Stream> {
+        match self {
+            Listener::Tcp(listener) => listener.accept().map(|(stream, _)| Stream::Tcp(stream)),
+            #[cfg(unix)]
+            Listener::Unix(socket) => socket
+                .listener
+                .accept()
+                .map(|(stream, _)| Stream::Unix(stream)),
+        }
+    }
+}
+
+/// Removes the socket file at `path` if no server is listening on it any more
+///
+/// # Errors
+///
+/// An `io::ErrorKind::AlreadyExists` error will occur if there is something other than a socket
+/// at `path`, and an `io::ErrorKind::AddrInUse` error if a server is listening on it
+#[cfg(unix)]
+fn remove_stale_socket(path: &std::path::Path) -> io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err),
+    };
+    if !metadata.file_type().is_socket() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} exists and is not a socket", path.display()),
+        ));
+    }
+    if UnixStream::connect(path).is_ok() {
+        return Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("A server is already listening on {}", path.display()),
+        ));
+    }
+    warn!("Replacing stale socket {}", path.display());
+    std::fs::remove_file(path)
+}
+
+#[cfg(unix)]
+impl Drop for UnixSocket {
+    fn drop(&mut self) {
+        if let Err(err) = std::fs::remove_file(&self.path) {
+            warn!("Failed to remove socket {}: {}", self.path.display(), err);
+        }
+    }
+}
+
+/// The address to connect to in order to reach a listener bound to `addr`, which may be a
+/// wildcard address that cannot be connected to everywhere
+pub fn connectable(addr: &KvsAddr) -> KvsAddr {
+    match addr {
+        KvsAddr::Tcp(addr) => {
+            let ip = match addr.ip() {
+                IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+                IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+                ip => ip,
+            };
+            KvsAddr::Tcp(SocketAddr::new(ip, addr.port()))
+        }
+        #[cfg(unix)]
+        addr => addr.clone(),
+    }
+}
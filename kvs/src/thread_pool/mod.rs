@@ -0,0 +1,30 @@
+use crate::Result;
+
+/// Runs jobs on a set of threads
+///
+/// A job that panics only takes down the job itself: the pool carries on running every other job
+/// and keeps accepting new ones.
+pub trait ThreadPool {
+    /// Creates a pool that runs jobs on `threads` threads (how many of those are started up front,
+    /// if any, is up to the implementation)
+    ///
+    /// # Errors
+    ///
+    /// A `KvsError::IoError` will occur if the threads cannot be started
+    fn new(threads: u32) -> Result<Self>
+    where
+        Self: Sized;
+
+    /// Queues `job` to run on one of the threads of the pool. Returns immediately.
+    fn spawn<F>(&self, job: F)
+    where
+        F: FnOnce() + Send + 'static;
+}
+
+pub use self::rayon::RayonThreadPool;
+pub use naive::NaiveThreadPool;
+pub use shared_queue::SharedQueueThreadPool;
+
+mod naive;
+mod rayon;
+mod shared_queue;
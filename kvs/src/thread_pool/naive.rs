@@ -0,0 +1,22 @@
+use super::ThreadPool;
+use crate::Result;
+use std::thread;
+
+/// A "pool" that starts a new thread for every job, however many jobs are running already
+pub struct NaiveThreadPool;
+
+impl ThreadPool for NaiveThreadPool {
+    /// Creates the pool. The number of threads is ignored.
+    fn new(_threads: u32) -> Result<NaiveThreadPool> {
+        Ok(NaiveThreadPool)
+    }
+
+    fn spawn<F>(&self, job: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        if let Err(err) = thread::Builder::new().spawn(job) {
+            error!("Failed to start a thread: {}", err);
+        }
+    }
+}
@@ -0,0 +1,27 @@
+use super::ThreadPool;
+use crate::{KvsError, Result};
+
+/// A work-stealing pool backed by `rayon`
+pub struct RayonThreadPool {
+    pool: rayon::ThreadPool,
+}
+
+impl ThreadPool for RayonThreadPool {
+    fn new(threads: u32) -> Result<RayonThreadPool> {
+        // without a panic handler rayon aborts the whole process when a job panics
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads as usize)
+            .thread_name(|_| "kvs-worker".to_owned())
+            .panic_handler(|_| error!("A job panicked"))
+            .build()
+            .map_err(|err| KvsError::InternalError(err.to_string()))?;
+        Ok(RayonThreadPool { pool })
+    }
+
+    fn spawn<F>(&self, job: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.pool.spawn(job);
+    }
+}
@@ -0,0 +1,58 @@
+use super::ThreadPool;
+use crate::Result;
+use crossbeam_channel::{Receiver, Sender};
+use std::thread;
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// A fixed number of threads taking jobs from a single shared queue
+///
+/// A thread whose job panics is replaced by a new one, so the pool never shrinks. Dropping the
+/// pool lets the threads finish the jobs already queued and then stop.
+pub struct SharedQueueThreadPool {
+    sender: Sender<Job>,
+}
+
+impl ThreadPool for SharedQueueThreadPool {
+    fn new(threads: u32) -> Result<SharedQueueThreadPool> {
+        let (sender, receiver) = crossbeam_channel::unbounded::<Job>();
+        for _ in 0..threads {
+            start_worker(Worker(receiver.clone()))?;
+        }
+        Ok(SharedQueueThreadPool { sender })
+    }
+
+    fn spawn<F>(&self, job: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        if self.sender.send(Box::new(job)).is_err() {
+            error!("Every thread of the pool has stopped");
+        }
+    }
+}
+
+/// The receiving end of the queue, owned by one thread of the pool. If the thread unwinds from a
+/// panicking job, dropping it starts a replacement thread.
+struct Worker(Receiver<Job>);
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            if let Err(err) = start_worker(Worker(self.0.clone())) {
+                error!("Failed to replace a panicked thread: {}", err);
+            }
+        }
+    }
+}
+
+fn start_worker(worker: Worker) -> Result<()> {
+    thread::Builder::new()
+        .name("kvs-worker".to_owned())
+        .spawn(move || {
+            for job in worker.0.iter() {
+                job();
+            }
+        })?;
+    Ok(())
+}
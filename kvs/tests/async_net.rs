@@ -0,0 +1,183 @@
+#![cfg(feature = "async")]
+
+use kvs::{
+    AsyncKvsClient, AsyncKvsServer, Capabilities, KvStore, KvsClient, KvsClientOptions, KvsEngine,
+    KvsError, KvsRequest, KvsResponse, Result,
+};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tempfile::TempDir;
+
+fn start_server() -> Result<(SocketAddr, TempDir)> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let server = AsyncKvsServer::bind(
+        "127.0.0.1:0".parse().unwrap(),
+        KvStore::open(temp_dir.path())?,
+    )?;
+    let addr = server.local_addr();
+    tokio::spawn(async move { server.serve().await });
+    Ok((addr, temp_dir))
+}
+
+// Requests sent through `AsyncKvsClient` should be answered by `AsyncKvsServer`
+#[tokio::test]
+async fn async_access_server() -> Result<()> {
+    let (addr, _temp_dir) = start_server()?;
+    let client = AsyncKvsClient::new(addr);
+
+    let request = KvsRequest::Set {
+        key: "key1".to_owned(),
+        value: "value1".to_owned(),
+    };
+    assert!(matches!(client.send(request).await?, KvsResponse::Set));
+    let request = KvsRequest::Get {
+        key: "key1".to_owned(),
+    };
+    match client.send(request).await? {
+        KvsResponse::Get { value } => assert_eq!(value, Some("value1".to_owned())),
+        response => panic!("unexpected response {:?}", response),
+    }
+    match client.remove("key2".to_owned()).await {
+        Err(KvsError::BadRemovalError) => {}
+        result => panic!("unexpected result {:?}", result),
+    }
+    client.remove("key1".to_owned()).await?;
+    assert_eq!(client.get("key1".to_owned()).await?, None);
+    Ok(())
+}
+
+// Many connections open at once should all be served, on the test's two runtime threads
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn async_many_connections() -> Result<()> {
+    let (addr, _temp_dir) = start_server()?;
+
+    let mut tasks = Vec::new();
+    for i in 0..500 {
+        tasks.push(tokio::spawn(async move {
+            let client = AsyncKvsClient::new(addr);
+            let request = KvsRequest::Set {
+                key: format!("key{}", i),
+                value: format!("value{}", i),
+            };
+            client.send(request).await
+        }));
+    }
+    for task in tasks {
+        assert!(matches!(task.await.unwrap()?, KvsResponse::Set));
+    }
+
+    let client = AsyncKvsClient::new(addr);
+    for i in (0..500).step_by(50) {
+        let request = KvsRequest::Get {
+            key: format!("key{}", i),
+        };
+        match client.send(request).await? {
+            KvsResponse::Get { value } => assert_eq!(value, Some(format!("value{}", i))),
+            response => panic!("unexpected response {:?}", response),
+        }
+    }
+    Ok(())
+}
+
+// Gets pipelined to `AsyncKvsServer` run concurrently, but every response should still be
+// matched back to its request
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn async_pipelining() -> Result<()> {
+    let (addr, _temp_dir) = start_server()?;
+
+    tokio::task::spawn_blocking(move || {
+        let client = KvsClientOptions::new()
+            .unordered_responses(true)
+            .build(addr.into());
+        let requests = (0..1000)
+            .map(|i| KvsRequest::Set {
+                key: format!("key{}", i),
+                value: format!("value{}", i),
+            })
+            .collect();
+        for response in client.send_all(requests)? {
+            assert!(matches!(response?, KvsResponse::Set));
+        }
+        let requests = (0..1000)
+            .map(|i| KvsRequest::Get {
+                key: format!("key{}", i),
+            })
+            .collect();
+        for (i, response) in client.send_all(requests)?.into_iter().enumerate() {
+            match response? {
+                KvsResponse::Get { value } => assert_eq!(value, Some(format!("value{}", i))),
+                response => panic!("unexpected response {:?}", response),
+            }
+        }
+        Ok(())
+    })
+    .await
+    .unwrap()
+}
+
+// Overwrites of one key pipelined to `AsyncKvsServer` should take effect in the order they were
+// sent, and a get pipelined after them should see the last, even with responses out of order
+// (which clients only get if they ask for them)
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn async_pipelined_overwrites() -> Result<()> {
+    let (addr, _temp_dir) = start_server()?;
+
+    tokio::task::spawn_blocking(move || {
+        let client = KvsClientOptions::new()
+            .unordered_responses(true)
+            .build(addr.into());
+        let unordered = Capabilities::UNORDERED_RESPONSES;
+        assert!(client.session()?.capabilities.contains(unordered));
+        let ordered = KvsClient::new(addr.into());
+        assert!(!ordered.session()?.capabilities.contains(unordered));
+        for round in 0..10 {
+            let mut requests: Vec<_> = (0..200)
+                .map(|i| KvsRequest::Set {
+                    key: "key".to_owned(),
+                    value: format!("{}-{}", round, i),
+                })
+                .collect();
+            requests.push(KvsRequest::Get {
+                key: "key".to_owned(),
+            });
+            let last = format!("{}-199", round);
+            match client.send_all(requests)?.pop().unwrap()? {
+                KvsResponse::Get { value } => assert_eq!(value, Some(last.clone())),
+                response => panic!("unexpected response {:?}", response),
+            }
+            assert_eq!(client.get("key".to_owned())?, Some(last));
+        }
+        Ok(())
+    })
+    .await
+    .unwrap()
+}
+
+// Shutting `AsyncKvsServer` down should stop it even with idle connections open, and leave
+// everything it was sent in the store
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn async_graceful_shutdown() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let server = AsyncKvsServer::bind(
+        "127.0.0.1:0".parse().unwrap(),
+        KvStore::open(temp_dir.path())?,
+    )?;
+    let addr = server.local_addr();
+    let handle = server.shutdown_handle();
+    let serving = tokio::spawn(server.serve());
+
+    let client = AsyncKvsClient::new(addr);
+    client.set("key".to_owned(), "value".to_owned()).await?;
+    let idle = KvsClient::new(addr.into());
+    assert_eq!(idle.get("key".to_owned())?, Some("value".to_owned()));
+
+    handle.shutdown(Duration::from_secs(10));
+    tokio::time::timeout(Duration::from_secs(5), serving)
+        .await
+        .expect("server did not shut down")
+        .unwrap()?;
+    assert!(client.get("key".to_owned()).await.is_err());
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key".to_owned())?, Some("value".to_owned()));
+    Ok(())
+}
@@ -1,4 +1,9 @@
-use kvs::{KvStore, KvsEngine, Result};
+use kvs::{
+    CompactionThreshold, Durability, KvStore, KvStoreOptions, KvsEngine, KvsError, Result,
+    SledKvsEngine,
+};
+use std::thread;
+use std::time::Duration;
 use tempfile::TempDir;
 use walkdir::WalkDir;
 
@@ -6,7 +11,7 @@ use walkdir::WalkDir;
 #[test]
 fn get_stored_value() -> Result<()> {
     let temp_dir = TempDir::new().expect("unable to create temporary working directory");
-    let mut store = KvStore::open(temp_dir.path())?;
+    let store = KvStore::open(temp_dir.path())?;
 
     store.set("key1".to_owned(), "value1".to_owned())?;
     store.set("key2".to_owned(), "value2".to_owned())?;
@@ -16,7 +21,7 @@ fn get_stored_value() -> Result<()> {
 
     // Open from disk again and check persistent data
     drop(store);
-    let mut store = KvStore::open(temp_dir.path())?;
+    let store = KvStore::open(temp_dir.path())?;
     assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
     assert_eq!(store.get("key2".to_owned())?, Some("value2".to_owned()));
 
@@ -27,7 +32,7 @@ fn get_stored_value() -> Result<()> {
 #[test]
 fn overwrite_value() -> Result<()> {
     let temp_dir = TempDir::new().expect("unable to create temporary working directory");
-    let mut store = KvStore::open(temp_dir.path())?;
+    let store = KvStore::open(temp_dir.path())?;
 
     store.set("key1".to_owned(), "value1".to_owned())?;
     assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
@@ -36,7 +41,7 @@ fn overwrite_value() -> Result<()> {
 
     // Open from disk again and check persistent data
     drop(store);
-    let mut store = KvStore::open(temp_dir.path())?;
+    let store = KvStore::open(temp_dir.path())?;
     assert_eq!(store.get("key1".to_owned())?, Some("value2".to_owned()));
     store.set("key1".to_owned(), "value3".to_owned())?;
     assert_eq!(store.get("key1".to_owned())?, Some("value3".to_owned()));
@@ -48,14 +53,14 @@ fn overwrite_value() -> Result<()> {
 #[test]
 fn get_non_existent_value() -> Result<()> {
     let temp_dir = TempDir::new().expect("unable to create temporary working directory");
-    let mut store = KvStore::open(temp_dir.path())?;
+    let store = KvStore::open(temp_dir.path())?;
 
     store.set("key1".to_owned(), "value1".to_owned())?;
     assert_eq!(store.get("key2".to_owned())?, None);
 
     // Open from disk again and check persistent data
     drop(store);
-    let mut store = KvStore::open(temp_dir.path())?;
+    let store = KvStore::open(temp_dir.path())?;
     assert_eq!(store.get("key2".to_owned())?, None);
 
     Ok(())
@@ -64,7 +69,7 @@ fn get_non_existent_value() -> Result<()> {
 #[test]
 fn remove_non_existent_key() -> Result<()> {
     let temp_dir = TempDir::new().expect("unable to create temporary working directory");
-    let mut store = KvStore::open(temp_dir.path())?;
+    let store = KvStore::open(temp_dir.path())?;
     assert!(store.remove("key1".to_owned()).is_err());
     Ok(())
 }
@@ -72,7 +77,7 @@ fn remove_non_existent_key() -> Result<()> {
 #[test]
 fn remove_key() -> Result<()> {
     let temp_dir = TempDir::new().expect("unable to create temporary working directory");
-    let mut store = KvStore::open(temp_dir.path())?;
+    let store = KvStore::open(temp_dir.path())?;
     store.set("key1".to_owned(), "value1".to_owned())?;
     assert!(store.remove("key1".to_owned()).is_ok());
     assert_eq!(store.get("key1".to_owned())?, None);
@@ -84,7 +89,7 @@ fn remove_key() -> Result<()> {
 #[test]
 fn compaction() -> Result<()> {
     let temp_dir = TempDir::new().expect("unable to create temporary working directory");
-    let mut store = KvStore::open(temp_dir.path())?;
+    let store = KvStore::open(temp_dir.path())?;
 
     let dir_size = || {
         let entries = WalkDir::new(temp_dir.path()).into_iter();
@@ -114,7 +119,7 @@ fn compaction() -> Result<()> {
 
         drop(store);
         // reopen and check content
-        let mut store = KvStore::open(temp_dir.path())?;
+        let store = KvStore::open(temp_dir.path())?;
         for key_id in 0..1000 {
             let key = format!("key{}", key_id);
             assert_eq!(store.get(key)?, Some(format!("{}", iter)));
@@ -124,3 +129,566 @@ fn compaction() -> Result<()> {
 
     panic!("No compaction detected");
 }
+
+// Should spread writes across several segment files and read them all back after reopening
+#[test]
+fn multiple_segments() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+
+    let value = "v".repeat(4096);
+    for key_id in 0..100 {
+        store.set(format!("key{}", key_id), format!("{}{}", value, key_id))?;
+    }
+
+    let segments = WalkDir::new(temp_dir.path())
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().extension() == Some("log".as_ref()))
+        .count();
+    assert!(segments > 1, "expected more than one segment");
+
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    for key_id in 0..100 {
+        let key = format!("key{}", key_id);
+        assert_eq!(store.get(key)?, Some(format!("{}{}", value, key_id)));
+    }
+    Ok(())
+}
+
+// Should ignore and clean up the temporary file of a compaction that was interrupted
+#[test]
+fn interrupted_compaction() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    drop(store);
+
+    let compact_path = temp_dir.path().join("compact.log");
+    std::fs::write(&compact_path, b"{\"Set\":{\"key\":\"key1\",\"val")?;
+
+    let store = KvStore::open(temp_dir.path())?;
+    assert!(!compact_path.exists());
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    Ok(())
+}
+
+// Should drop a record that was only partly written before a crash
+#[test]
+fn torn_write_recovery() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    drop(store);
+
+    let segment_path = temp_dir.path().join("1.log");
+    let mut segment = std::fs::read(&segment_path)?;
+    let complete_len = segment.len();
+    segment.extend_from_slice(&[42, 0, 0, 0, 1, 2, 3]);
+    std::fs::write(&segment_path, &segment)?;
+
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(std::fs::metadata(&segment_path)?.len(), complete_len as u64);
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, Some("value2".to_owned()));
+    store.set("key3".to_owned(), "value3".to_owned())?;
+
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key3".to_owned())?, Some("value3".to_owned()));
+    Ok(())
+}
+
+// Should refuse to open a log that is damaged before its final record
+#[test]
+fn detect_corruption() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    drop(store);
+
+    let segment_path = temp_dir.path().join("1.log");
+    let mut segment = std::fs::read(&segment_path)?;
+    segment[20] ^= 0xff;
+    std::fs::write(&segment_path, &segment)?;
+
+    match KvStore::open(temp_dir.path()) {
+        Err(KvsError::CorruptionError(1, 8)) => Ok(()),
+        Err(err) => panic!("unexpected error: {}", err),
+        Ok(_) => panic!("corruption was not detected"),
+    }
+}
+
+// Should convert a log written in the old newline-delimited JSON format on first open
+#[test]
+fn upgrade_legacy_log() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let legacy_path = temp_dir.path().join("kvs.log");
+    std::fs::write(
+        &legacy_path,
+        concat!(
+            "{\"Set\":{\"key\":\"key1\",\"value\":\"value1\"}}\n",
+            "{\"Set\":{\"key\":\"key2\",\"value\":\"value2\"}}\n",
+            "{\"Set\":{\"key\":\"key1\",\"value\":\"value3\"}}\n",
+            "{\"Remove\":{\"key\":\"key2\"}}\n",
+        ),
+    )?;
+
+    let store = KvStore::open(temp_dir.path())?;
+    assert!(!legacy_path.exists());
+    assert_eq!(store.get("key1".to_owned())?, Some("value3".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, None);
+
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value3".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, None);
+    Ok(())
+}
+
+// Should read and upgrade segments written in format version 1, where records held bincode
+// encoded network requests
+#[test]
+fn upgrade_v1_segment() -> Result<()> {
+    fn v1_record(variant: u32, fields: &[&str]) -> Vec<u8> {
+        let mut payload = variant.to_le_bytes().to_vec();
+        for field in fields {
+            payload.extend_from_slice(&(field.len() as u64).to_le_bytes());
+            payload.extend_from_slice(field.as_bytes());
+        }
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&payload);
+        let mut record = (payload.len() as u32).to_le_bytes().to_vec();
+        record.extend_from_slice(&hasher.finalize().to_le_bytes());
+        record.extend_from_slice(&payload);
+        record
+    }
+
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut segment = b"KVSL".to_vec();
+    segment.extend_from_slice(&1u32.to_le_bytes());
+    segment.extend_from_slice(&v1_record(1, &["key1", "value1"]));
+    segment.extend_from_slice(&v1_record(1, &["key2", "value2"]));
+    segment.extend_from_slice(&v1_record(2, &["key1"]));
+    std::fs::write(temp_dir.path().join("1.log"), &segment)?;
+
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, None);
+    assert_eq!(store.get("key2".to_owned())?, Some("value2".to_owned()));
+    store.set("key3".to_owned(), "value3".to_owned())?;
+
+    drop(store);
+    let version = std::fs::read(temp_dir.path().join("1.log"))?[4..8].to_vec();
+    assert_eq!(version, 2u32.to_le_bytes().to_vec());
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key2".to_owned())?, Some("value2".to_owned()));
+    assert_eq!(store.get("key3".to_owned())?, Some("value3".to_owned()));
+    Ok(())
+}
+
+// Should write a hint file when compacting, and reopen correctly both with and without it
+#[test]
+fn hint_file() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let hint_path = temp_dir.path().join("kvs.hint");
+    let store = KvStore::open(temp_dir.path())?;
+
+    let mut iter = 0;
+    while !hint_path.exists() {
+        iter += 1;
+        assert!(iter < 1000, "No compaction detected");
+        for key_id in 0..1000 {
+            store.set(format!("key{}", key_id), format!("{}", iter))?;
+        }
+    }
+    // writes after the compaction must be replayed on top of the hint
+    store.set("key0".to_owned(), "after".to_owned())?;
+    store.remove("key1".to_owned())?;
+
+    let check = |store: &KvStore| -> Result<()> {
+        assert_eq!(store.get("key0".to_owned())?, Some("after".to_owned()));
+        assert_eq!(store.get("key1".to_owned())?, None);
+        for key_id in 2..1000 {
+            let key = format!("key{}", key_id);
+            assert_eq!(store.get(key)?, Some(format!("{}", iter)));
+        }
+        Ok(())
+    };
+
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    check(&store)?;
+
+    // a damaged hint falls back to replaying every segment
+    drop(store);
+    std::fs::write(&hint_path, b"KVSH garbage")?;
+    let store = KvStore::open(temp_dir.path())?;
+    check(&store)?;
+    Ok(())
+}
+
+// Writes keep going while a compaction runs, and an explicit compaction reports its progress
+#[test]
+fn background_compaction() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+
+    for iter in 0..10 {
+        for key_id in 0..1000 {
+            store.set(format!("key{}", key_id), format!("{}", iter))?;
+        }
+    }
+    let before = store.compaction_progress();
+    store.compact()?;
+    let progress = store.compaction_progress();
+    assert!(!progress.running);
+    assert_eq!(progress.completed, before.completed + 1);
+    assert_eq!(progress.entries_copied, progress.entries_total);
+
+    for key_id in 0..1000 {
+        let key = format!("key{}", key_id);
+        assert_eq!(store.get(key)?, Some("9".to_owned()));
+    }
+
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    for key_id in 0..1000 {
+        let key = format!("key{}", key_id);
+        assert_eq!(store.get(key)?, Some("9".to_owned()));
+    }
+    Ok(())
+}
+
+// Options decide whether a store may be created or must be new, and how big segments get
+#[test]
+fn store_options() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+
+    let missing = KvStoreOptions::new()
+        .create_if_missing(false)
+        .open(temp_dir.path());
+    assert!(matches!(missing, Err(KvsError::StoreMissingError(_))));
+
+    let store = KvStoreOptions::new()
+        .error_if_exists(true)
+        .segment_bytes(1024)
+        .compaction_threshold(CompactionThreshold::StaleRatio(0.9))
+        .open(temp_dir.path())?;
+    for key_id in 0..100 {
+        store.set(format!("key{}", key_id), "value".repeat(10))?;
+    }
+    drop(store);
+
+    let segments = WalkDir::new(temp_dir.path())
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().extension() == Some("log".as_ref()))
+        .count();
+    assert!(segments > 1, "expected more than one segment");
+
+    let exists = KvStoreOptions::new()
+        .error_if_exists(true)
+        .open(temp_dir.path());
+    assert!(matches!(exists, Err(KvsError::StoreExistsError(_))));
+    Ok(())
+}
+
+// A stale ratio that would compact on every write, or never, is refused
+#[test]
+fn bad_stale_ratio() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    for &ratio in [0.0, -0.5, 1.5, f64::NAN].iter() {
+        let result = KvStoreOptions::new()
+            .compaction_threshold(CompactionThreshold::StaleRatio(ratio))
+            .open(temp_dir.path());
+        assert!(matches!(result, Err(KvsError::BadOptionError(_))));
+    }
+    KvStoreOptions::new()
+        .compaction_threshold(CompactionThreshold::StaleRatio(1.0))
+        .open(temp_dir.path())?;
+    Ok(())
+}
+
+// A read-only store serves reads but refuses writes and compactions
+#[test]
+fn read_only() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    drop(store);
+
+    let store = KvStoreOptions::new()
+        .read_only(true)
+        .open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert!(matches!(
+        store.set("key2".to_owned(), "value2".to_owned()),
+        Err(KvsError::ReadOnlyError)
+    ));
+    assert!(matches!(
+        store.remove("key1".to_owned()),
+        Err(KvsError::ReadOnlyError)
+    ));
+    assert!(matches!(store.compact(), Err(KvsError::ReadOnlyError)));
+    Ok(())
+}
+
+// Live data alone never triggers compaction, however large it gets
+#[test]
+fn compaction_ignores_live_data() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+
+    let value = "v".repeat(1024);
+    for key_id in 0..4096 {
+        store.set(format!("key{}", key_id), value.clone())?;
+    }
+    assert_eq!(store.compaction_progress().completed, 0);
+    assert!(!temp_dir.path().join("kvs.hint").exists());
+
+    // overwriting the same data leaves enough of it stale to compact
+    for key_id in 0..4096 {
+        store.set(format!("key{}", key_id), value.clone())?;
+    }
+    drop(store);
+    assert!(temp_dir.path().join("kvs.hint").exists());
+    Ok(())
+}
+
+// Writes that the durability mode promised to flush are in the files an engine leaves behind
+// when it disappears without being dropped, the way it would if the process crashed
+#[test]
+fn durability() -> Result<()> {
+    let modes = [
+        Durability::EveryWrite,
+        Durability::GroupCommit(Duration::from_millis(10)),
+    ];
+    for &durability in modes.iter() {
+        let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+        let crash_dir = TempDir::new().expect("unable to create temporary working directory");
+        let store = KvStoreOptions::new()
+            .durability(durability)
+            .open(temp_dir.path())?;
+        check_durability(&store, durability)?;
+        copy_dir(temp_dir.path(), crash_dir.path());
+        std::mem::forget(store);
+        let store = KvStore::open(crash_dir.path())?;
+        assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+        assert_eq!(store.get("key2".to_owned())?, None);
+
+        let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+        let crash_dir = TempDir::new().expect("unable to create temporary working directory");
+        let store = SledKvsEngine::open_with_durability(temp_dir.path(), durability)?;
+        check_durability(&store, durability)?;
+        copy_dir(temp_dir.path(), crash_dir.path());
+        std::mem::forget(store);
+        let store = SledKvsEngine::open(crash_dir.path())?;
+        assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+        assert_eq!(store.get("key2".to_owned())?, None);
+    }
+    Ok(())
+}
+
+fn check_durability<E: KvsEngine>(store: &E, durability: Durability) -> Result<()> {
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    store.remove("key2".to_owned())?;
+    if let Durability::GroupCommit(interval) = durability {
+        thread::sleep(interval * 10);
+    }
+    Ok(())
+}
+
+fn copy_dir(from: &std::path::Path, to: &std::path::Path) {
+    for entry in WalkDir::new(from).min_depth(1) {
+        let entry = entry.expect("unable to walk directory");
+        let target = to.join(entry.path().strip_prefix(from).unwrap());
+        if entry.file_type().is_dir() {
+            std::fs::create_dir_all(target).expect("unable to create directory");
+        } else {
+            std::fs::copy(entry.path(), target).expect("unable to copy file");
+        }
+    }
+}
+
+// A batch that fails to start a new segment partway leaves none of its records behind, so a
+// write that was reported as failed does not turn up once the store is opened again
+#[test]
+fn failed_segment_roll() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStoreOptions::new()
+        .durability(Durability::EveryWrite)
+        .segment_bytes(1024)
+        .open(temp_dir.path())?;
+    // the next segment cannot be created while a directory is in its place
+    let blocked = temp_dir.path().join("2.log");
+    std::fs::create_dir(&blocked)?;
+
+    let handles: Vec<_> = (0..8)
+        .map(|thread_id| {
+            let store = store.clone();
+            thread::spawn(move || {
+                (0..50)
+                    .map(|key_id| {
+                        let key = format!("key{}-{}", thread_id, key_id);
+                        let written = store.set(key.clone(), "value".to_owned()).is_ok();
+                        (key, written)
+                    })
+                    .collect::<Vec<_>>()
+            })
+        })
+        .collect();
+    let outcomes: Vec<_> = handles
+        .into_iter()
+        .flat_map(|handle| handle.join().unwrap())
+        .collect();
+    assert!(outcomes.iter().any(|(_, written)| !written));
+
+    drop(store);
+    std::fs::remove_dir(&blocked)?;
+    let store = KvStore::open(temp_dir.path())?;
+    for (key, written) in outcomes {
+        let expected = if written {
+            Some("value".to_owned())
+        } else {
+            None
+        };
+        assert_eq!(store.get(key)?, expected);
+    }
+    Ok(())
+}
+
+// A group commit without an interval would flush in a busy loop, so both engines refuse it
+#[test]
+fn zero_group_commit() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let durability = Durability::GroupCommit(Duration::from_secs(0));
+    let result = KvStoreOptions::new()
+        .durability(durability)
+        .open(temp_dir.path());
+    assert!(matches!(result, Err(KvsError::BadOptionError(_))));
+    let result = SledKvsEngine::open_with_durability(temp_dir.path(), durability);
+    assert!(matches!(result, Err(KvsError::BadOptionError(_))));
+    Ok(())
+}
+
+// Concurrent writers through cloned handles are all committed, and each sees its own outcome
+#[test]
+fn concurrent_writers() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStoreOptions::new()
+        .durability(Durability::EveryWrite)
+        .open(temp_dir.path())?;
+
+    let handles: Vec<_> = (0..8)
+        .map(|thread_id| {
+            let store = store.clone();
+            thread::spawn(move || -> Result<()> {
+                for key_id in 0..100 {
+                    let key = format!("key{}-{}", thread_id, key_id);
+                    store.set(key.clone(), format!("{}", key_id))?;
+                    if key_id % 2 == 0 {
+                        store.remove(key.clone())?;
+                        assert!(matches!(store.remove(key), Err(KvsError::BadRemovalError)));
+                    }
+                }
+                Ok(())
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap()?;
+    }
+
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    for thread_id in 0..8 {
+        for key_id in 0..100 {
+            let key = format!("key{}-{}", thread_id, key_id);
+            let expected = if key_id % 2 == 0 {
+                None
+            } else {
+                Some(format!("{}", key_id))
+            };
+            assert_eq!(store.get(key)?, expected);
+        }
+    }
+    Ok(())
+}
+
+// Clones of either engine can be used from many threads at once
+#[test]
+fn shared_engines() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    check_shared(KvStore::open(temp_dir.path())?)?;
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    check_shared(SledKvsEngine::open(temp_dir.path())?)?;
+    Ok(())
+}
+
+fn check_shared<E: KvsEngine>(engine: E) -> Result<()> {
+    let handles: Vec<_> = (0..8)
+        .map(|thread_id| {
+            let engine = engine.clone();
+            thread::spawn(move || -> Result<()> {
+                for key_id in 0..100 {
+                    let key = format!("key{}", key_id);
+                    engine.set(key.clone(), format!("{}", thread_id))?;
+                    assert!(engine.get(key)?.is_some());
+                }
+                Ok(())
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap()?;
+    }
+    for key_id in 0..100 {
+        let value = engine
+            .get(format!("key{}", key_id))?
+            .expect("key is missing");
+        assert!(value.parse::<u32>().unwrap() < 8);
+    }
+    Ok(())
+}
+
+// Readers on other threads always find every key while it is overwritten and compacted
+#[test]
+fn reads_during_compaction() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    for key_id in 0..100 {
+        store.set(format!("key{}", key_id), "0".to_owned())?;
+    }
+
+    let done = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let readers: Vec<_> = (0..4)
+        .map(|_| {
+            let store = store.clone();
+            let done = done.clone();
+            thread::spawn(move || -> Result<()> {
+                while !done.load(std::sync::atomic::Ordering::SeqCst) {
+                    for key_id in 0..100 {
+                        assert!(store.get(format!("key{}", key_id))?.is_some());
+                    }
+                }
+                Ok(())
+            })
+        })
+        .collect();
+
+    for iter in 1..20 {
+        for key_id in 0..100 {
+            store.set(format!("key{}", key_id), format!("{}", iter))?;
+        }
+        store.compact()?;
+    }
+    done.store(true, std::sync::atomic::Ordering::SeqCst);
+    for reader in readers {
+        reader.join().unwrap()?;
+    }
+    Ok(())
+}
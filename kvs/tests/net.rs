@@ -0,0 +1,603 @@
+use kvs::{
+    Capabilities, ErrorCode, Hello, HelloReply, KvStore, KvStoreOptions, KvsAddr, KvsClient,
+    KvsClientOptions, KvsEngine, KvsError, KvsRequest, KvsResponse, KvsServer, RequestEnvelope,
+    ResponseEnvelope, Result, SharedQueueThreadPool, ShutdownHandle, ThreadPool,
+    MIN_PROTOCOL_VERSION, PROTOCOL_VERSION, UNKNOWN_REQUEST_ID,
+};
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+// Starts a server with `threads` threads and a store of its own on a free port of localhost
+fn start_server(threads: u32) -> Result<(KvsAddr, ShutdownHandle, TempDir)> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let server = KvsServer::bind(
+        "127.0.0.1:0".parse().unwrap(),
+        KvStore::open(temp_dir.path())?,
+        SharedQueueThreadPool::new(threads)?,
+    )?;
+    let (addr, handle) = run_server(server);
+    Ok((addr, handle, temp_dir))
+}
+
+// Serves `server` on a thread of its own
+fn run_server<E: KvsEngine>(
+    server: KvsServer<E, SharedQueueThreadPool>,
+) -> (KvsAddr, ShutdownHandle) {
+    let addr = server.local_addr();
+    let handle = server.shutdown_handle();
+    thread::spawn(move || server.serve());
+    (addr, handle)
+}
+
+// A client should send all of its requests over a single connection. The server only has one
+// thread, which the first connection keeps busy for as long as it stays open, so a client opening
+// a connection per request would never get a second answer.
+#[test]
+fn persistent_connection() -> Result<()> {
+    let (addr, handle, _temp_dir) = start_server(1)?;
+
+    let client = KvsClient::new(addr);
+    for i in 0..100 {
+        let request = KvsRequest::Set {
+            key: format!("key{}", i),
+            value: format!("value{}", i),
+        };
+        assert!(matches!(client.send(request)?, KvsResponse::Set));
+    }
+    for i in 0..100 {
+        let request = KvsRequest::Get {
+            key: format!("key{}", i),
+        };
+        match client.send(request)? {
+            KvsResponse::Get { value } => assert_eq!(value, Some(format!("value{}", i))),
+            response => panic!("unexpected response {:?}", response),
+        }
+    }
+    handle.shutdown(Duration::from_secs(1));
+    Ok(())
+}
+
+// A pipelined batch should get every response back, matched to the right request, even when the
+// batch is far larger than the socket buffers
+#[test]
+fn pipelining() -> Result<()> {
+    let (addr, handle, _temp_dir) = start_server(2)?;
+
+    let client = KvsClient::new(addr);
+    let value = "v".repeat(1024);
+    let requests = (0..5000)
+        .map(|i| KvsRequest::Set {
+            key: format!("key{}", i),
+            value: format!("{}{}", value, i),
+        })
+        .collect();
+    for response in client.send_all(requests)? {
+        assert!(matches!(response?, KvsResponse::Set));
+    }
+
+    let requests = (0..5000)
+        .map(|i| KvsRequest::Get {
+            key: format!("key{}", i),
+        })
+        .collect();
+    for (i, response) in client.send_all(requests)?.into_iter().enumerate() {
+        match response? {
+            KvsResponse::Get { value: received } => {
+                assert_eq!(received, Some(format!("{}{}", value, i)))
+            }
+            response => panic!("unexpected response {:?}", response),
+        }
+    }
+
+    let requests = (0..100)
+        .map(|i| KvsRequest::Remove {
+            key: format!("key{}", i * 2),
+        })
+        .collect();
+    let mut answered = [false; 100];
+    client.send_all_unordered(requests, |index, response| {
+        assert!(matches!(response, Ok(KvsResponse::Remove)));
+        answered[index] = true;
+    })?;
+    assert!(answered.iter().all(|&answered| answered));
+
+    // single requests keep working on the same connection
+    let request = KvsRequest::Get {
+        key: "key0".to_owned(),
+    };
+    assert!(matches!(
+        client.send(request)?,
+        KvsResponse::Get { value: None }
+    ));
+    handle.shutdown(Duration::from_secs(1));
+    Ok(())
+}
+
+fn write_raw_frame(stream: &mut TcpStream, payload: &[u8]) -> Result<()> {
+    stream.write_all(&(payload.len() as u32).to_le_bytes())?;
+    stream.write_all(payload)?;
+    Ok(())
+}
+
+fn read_raw_frame(stream: &mut TcpStream) -> Result<Vec<u8>> {
+    let mut length = [0; 4];
+    stream.read_exact(&mut length)?;
+    let mut payload = vec![0; u32::from_le_bytes(length) as usize];
+    stream.read_exact(&mut payload)?;
+    Ok(payload)
+}
+
+// Sends `payload` as the first frame of a new connection and decodes the server's reply
+fn raw_handshake(addr: &KvsAddr, payload: &[u8]) -> Result<(TcpStream, HelloReply)> {
+    let mut stream = TcpStream::connect(addr.to_string())?;
+    write_raw_frame(&mut stream, payload)?;
+    let reply = bincode::deserialize(&read_raw_frame(&mut stream)?)?;
+    Ok((stream, reply))
+}
+
+// Clients should find out which protocol version and capabilities they got, older peers should
+// still be answered in the protocol they speak, and peers that cannot talk to the server should be
+// told why
+#[test]
+fn handshake() -> Result<()> {
+    let (addr, handle, _temp_dir) = start_server(2)?;
+
+    let session = KvsClient::new(addr.clone()).session()?;
+    assert_eq!(session.version, PROTOCOL_VERSION);
+    assert!(session.capabilities.contains(Capabilities::PIPELINING));
+    assert!(!session
+        .capabilities
+        .contains(Capabilities::UNORDERED_RESPONSES));
+
+    let mut hello = Hello::new(Capabilities::empty());
+    hello.min_version = PROTOCOL_VERSION + 1;
+    hello.max_version = PROTOCOL_VERSION + 2;
+    match raw_handshake(&addr, &bincode::serialize(&hello)?)?.1 {
+        HelloReply::Rejected {
+            min_version,
+            max_version,
+            ..
+        } => assert_eq!(
+            (min_version, max_version),
+            (MIN_PROTOCOL_VERSION, PROTOCOL_VERSION)
+        ),
+        reply => panic!("unexpected handshake reply {:?}", reply),
+    }
+
+    // version 1 peers are still served, with errors in the layout they know
+    let mut hello = Hello::new(Capabilities::empty());
+    hello.max_version = 1;
+    let (mut stream, reply) = raw_handshake(&addr, &bincode::serialize(&hello)?)?;
+    assert_eq!(reply.into_session()?.version, 1);
+    let request = RequestEnvelope {
+        id: 1,
+        request: KvsRequest::Remove {
+            key: "key".to_owned(),
+        },
+    };
+    write_raw_frame(&mut stream, &bincode::serialize(&request)?)?;
+    let response: ResponseEnvelope = bincode::deserialize(&read_raw_frame(&mut stream)?)?;
+    match response.response {
+        KvsResponse::Error { message } => assert_eq!(message, "Key not found"),
+        response => panic!("unexpected response {:?}", response),
+    }
+
+    let (_, reply) = raw_handshake(&addr, b"GET / HTTP/1.1")?;
+    match reply.into_session() {
+        Err(KvsError::HandshakeError(_)) => {}
+        result => panic!("unexpected handshake result {:?}", result),
+    }
+    handle.shutdown(Duration::from_secs(1));
+    Ok(())
+}
+
+// Requests the server cannot decode should be answered with an error instead of being dropped
+#[test]
+fn malformed_requests() -> Result<()> {
+    let (addr, handle, _temp_dir) = start_server(2)?;
+
+    let hello = bincode::serialize(&Hello::new(Capabilities::empty()))?;
+    let (mut stream, reply) = raw_handshake(&addr, &hello)?;
+    reply.into_session()?;
+
+    // a request variant the server does not know, as a newer client might send
+    let mut request = 7u64.to_le_bytes().to_vec();
+    request.extend_from_slice(&99u32.to_le_bytes());
+    write_raw_frame(&mut stream, &request)?;
+    let response: ResponseEnvelope = bincode::deserialize(&read_raw_frame(&mut stream)?)?;
+    assert_eq!(response.id, 7);
+    assert!(matches!(
+        response.response,
+        KvsResponse::Failure {
+            code: ErrorCode::MalformedRequest,
+            ..
+        }
+    ));
+
+    // the connection carries on after a request that did not decode
+    let request = RequestEnvelope {
+        id: 8,
+        request: KvsRequest::Get {
+            key: "key".to_owned(),
+        },
+    };
+    write_raw_frame(&mut stream, &bincode::serialize(&request)?)?;
+    let response: ResponseEnvelope = bincode::deserialize(&read_raw_frame(&mut stream)?)?;
+    assert_eq!(response.id, 8);
+    assert!(matches!(
+        response.response,
+        KvsResponse::Get { value: None }
+    ));
+
+    // a frame cut short by the client closing the connection
+    stream.write_all(&[16, 0])?;
+    stream.shutdown(Shutdown::Write)?;
+    let response: ResponseEnvelope = bincode::deserialize(&read_raw_frame(&mut stream)?)?;
+    assert_eq!(response.id, UNKNOWN_REQUEST_ID);
+    assert!(matches!(
+        response.response,
+        KvsResponse::Failure {
+            code: ErrorCode::MalformedRequest,
+            ..
+        }
+    ));
+    handle.shutdown(Duration::from_secs(1));
+    Ok(())
+}
+
+// Garbage from the server should fail the request with a decode error instead of panicking
+#[test]
+fn malformed_responses() -> Result<()> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let addr = listener.local_addr()?;
+    thread::spawn(move || -> Result<()> {
+        let (mut stream, _) = listener.accept()?;
+        read_raw_frame(&mut stream)?;
+        let reply = HelloReply::Accepted {
+            version: PROTOCOL_VERSION,
+            capabilities: Capabilities::empty(),
+        };
+        write_raw_frame(&mut stream, &bincode::serialize(&reply)?)?;
+        read_raw_frame(&mut stream)?;
+        write_raw_frame(&mut stream, &[0xff; 5])?;
+        Ok(())
+    });
+
+    let client = KvsClient::new(addr.into());
+    let request = KvsRequest::Get {
+        key: "key".to_owned(),
+    };
+    match client.send(request) {
+        Err(KvsError::DecodeError(_, length)) => assert_eq!(length, 5),
+        result => panic!("unexpected result {:?}", result),
+    }
+    Ok(())
+}
+
+// Failures on the server should come back to the client as the matching `KvsError`
+#[test]
+fn error_codes() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    KvStore::open(temp_dir.path())?.set("key".to_owned(), "value".to_owned())?;
+    let store = KvStoreOptions::new()
+        .read_only(true)
+        .open(temp_dir.path())?;
+    let (addr, handle) = run_server(KvsServer::bind(
+        "127.0.0.1:0".parse().unwrap(),
+        store,
+        SharedQueueThreadPool::new(2)?,
+    )?);
+
+    let client = KvsClient::new(addr);
+    let request = KvsRequest::Remove {
+        key: "missing".to_owned(),
+    };
+    match client.send(request) {
+        Err(KvsError::ReadOnlyError) => {}
+        result => panic!("unexpected result {:?}", result),
+    }
+
+    // in a batch, one failed request does not fail the others
+    let requests = vec![
+        KvsRequest::Set {
+            key: "key".to_owned(),
+            value: "other".to_owned(),
+        },
+        KvsRequest::Get {
+            key: "key".to_owned(),
+        },
+    ];
+    let responses = client.send_all(requests)?;
+    assert!(matches!(responses[0], Err(KvsError::ReadOnlyError)));
+    match &responses[1] {
+        Ok(KvsResponse::Get { value }) => assert_eq!(value.as_deref(), Some("value")),
+        result => panic!("unexpected result {:?}", result),
+    }
+    handle.shutdown(Duration::from_secs(1));
+    Ok(())
+}
+
+// Code generic over `KvsEngine` should behave the same with a local store as with a client of a
+// remote one
+#[test]
+fn client_as_engine() -> Result<()> {
+    fn exercise<E: KvsEngine>(engine: E) -> Result<()> {
+        let other = engine.clone();
+        engine.set("key".to_owned(), "value".to_owned())?;
+        assert_eq!(other.get("key".to_owned())?, Some("value".to_owned()));
+        other.remove("key".to_owned())?;
+        assert_eq!(engine.get("key".to_owned())?, None);
+        match engine.remove("key".to_owned()) {
+            Err(KvsError::BadRemovalError) => Ok(()),
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    exercise(KvStore::open(temp_dir.path())?)?;
+
+    let (addr, handle, _temp_dir) = start_server(2)?;
+    exercise(KvsClient::new(addr))?;
+    handle.shutdown(Duration::from_secs(1));
+    Ok(())
+}
+
+// Plays the server's side of the handshake on a connection accepted by a test
+fn accept_raw(listener: &TcpListener) -> Result<TcpStream> {
+    let (mut stream, _) = listener.accept()?;
+    read_raw_frame(&mut stream)?;
+    let reply = HelloReply::Accepted {
+        version: PROTOCOL_VERSION,
+        capabilities: Capabilities::empty(),
+    };
+    write_raw_frame(&mut stream, &bincode::serialize(&reply)?)?;
+    Ok(stream)
+}
+
+// Answers the next request on a connection accepted by a test with `value`
+fn answer_get(stream: &mut TcpStream, value: &str) -> Result<()> {
+    let envelope: RequestEnvelope = bincode::deserialize(&read_raw_frame(stream)?)?;
+    let response = ResponseEnvelope {
+        id: envelope.id,
+        response: KvsResponse::Get {
+            value: Some(value.to_owned()),
+        },
+    };
+    write_raw_frame(stream, &bincode::serialize(&response)?)
+}
+
+// A server that never answers should fail a request once the read timeout passes, rather than
+// blocking the client forever
+#[test]
+fn timeouts() -> Result<()> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let addr = listener.local_addr()?;
+    thread::spawn(move || -> Result<()> {
+        let _stream = accept_raw(&listener)?;
+        thread::sleep(Duration::from_secs(30));
+        Ok(())
+    });
+
+    let client = KvsClientOptions::new()
+        .read_timeout(Some(Duration::from_millis(200)))
+        .retries(0)
+        .build(addr.into());
+    let start = Instant::now();
+    match client.get("key".to_owned()) {
+        Err(KvsError::IoError(_)) => {}
+        result => panic!("unexpected result {:?}", result),
+    }
+    assert!(start.elapsed() < Duration::from_secs(5));
+    Ok(())
+}
+
+// A pooled connection the server has closed should be dropped instead of being used, and a get
+// that fails along with its connection should be retried on a new one
+#[test]
+fn dead_connections() -> Result<()> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let addr = listener.local_addr()?;
+    thread::spawn(move || -> Result<()> {
+        // closed once its request is answered
+        answer_get(&mut accept_raw(&listener)?, "first")?;
+        // closed while its second request is in flight
+        let mut stream = accept_raw(&listener)?;
+        answer_get(&mut stream, "second")?;
+        read_raw_frame(&mut stream)?;
+        drop(stream);
+        answer_get(&mut accept_raw(&listener)?, "third")?;
+        Ok(())
+    });
+
+    let client = KvsClientOptions::new()
+        .retries(1)
+        .backoff(Duration::from_millis(10), Duration::from_millis(10))
+        .build(addr.into());
+    assert_eq!(client.get("key".to_owned())?, Some("first".to_owned()));
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(client.get("key".to_owned())?, Some("second".to_owned()));
+    assert_eq!(client.get("key".to_owned())?, Some("third".to_owned()));
+    Ok(())
+}
+
+// A file operation failing on the server is not a broken connection, so a get that fails with it
+// should not be retried
+#[test]
+fn server_io_errors() -> Result<()> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let addr = listener.local_addr()?;
+    let requests = Arc::new(AtomicUsize::new(0));
+    let counted = requests.clone();
+    thread::spawn(move || -> Result<()> {
+        loop {
+            let mut stream = accept_raw(&listener)?;
+            let requests = counted.clone();
+            thread::spawn(move || -> Result<()> {
+                loop {
+                    let envelope: RequestEnvelope =
+                        bincode::deserialize(&read_raw_frame(&mut stream)?)?;
+                    requests.fetch_add(1, Ordering::SeqCst);
+                    let response = ResponseEnvelope {
+                        id: envelope.id,
+                        response: KvsResponse::Failure {
+                            code: ErrorCode::Io,
+                            message: "disk full".to_owned(),
+                        },
+                    };
+                    write_raw_frame(&mut stream, &bincode::serialize(&response)?)?;
+                }
+            });
+        }
+    });
+
+    let client = KvsClientOptions::new()
+        .retries(2)
+        .backoff(Duration::from_millis(10), Duration::from_millis(10))
+        .build(addr.into());
+    match client.get("key".to_owned()) {
+        Err(KvsError::ServerError {
+            code: ErrorCode::Io,
+            ..
+        }) => {}
+        result => panic!("unexpected result {:?}", result),
+    }
+    assert_eq!(requests.load(Ordering::SeqCst), 1);
+    Ok(())
+}
+
+// Shutting down should close idle connections, stop accepting new ones and make `serve` return
+// with every change still in the store
+#[test]
+fn graceful_shutdown() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let server = KvsServer::bind(
+        "127.0.0.1:0".parse().unwrap(),
+        KvStore::open(temp_dir.path())?,
+        SharedQueueThreadPool::new(2)?,
+    )?;
+    let addr = server.local_addr();
+    let handle = server.shutdown_handle();
+    let serving = thread::spawn(move || server.serve());
+
+    let client = KvsClient::new(addr.clone());
+    client.set("key".to_owned(), "value".to_owned())?;
+    // keeps a connection open, idle in the pool
+    KvsClient::new(addr).session()?;
+
+    let start = Instant::now();
+    handle.shutdown(Duration::from_secs(10));
+    assert!(handle.is_shutdown());
+    serving.join().expect("server panicked")?;
+    assert!(start.elapsed() < Duration::from_secs(5));
+
+    match client.get("key".to_owned()) {
+        Err(KvsError::IoError(_)) => {}
+        result => panic!("unexpected result {:?}", result),
+    }
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key".to_owned())?, Some("value".to_owned()));
+    Ok(())
+}
+
+// Idle connections should be closed after the server's idle timeout, so that clients keeping
+// connections around cannot hold every thread of the pool and lock other clients out
+#[test]
+fn idle_connections() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut server = KvsServer::bind(
+        "127.0.0.1:0".parse().unwrap(),
+        KvStore::open(temp_dir.path())?,
+        SharedQueueThreadPool::new(2)?,
+    )?;
+    server.idle_timeout(Some(Duration::from_millis(200)));
+    let (addr, handle) = run_server(server);
+
+    let idle: Vec<_> = (0..2).map(|_| KvsClient::new(addr.clone())).collect();
+    for (i, client) in idle.iter().enumerate() {
+        client.set(format!("key{}", i), format!("value{}", i))?;
+    }
+    let start = Instant::now();
+    let client = KvsClientOptions::new()
+        .read_timeout(Some(Duration::from_secs(5)))
+        .build(addr);
+    assert_eq!(client.get("key0".to_owned())?, Some("value0".to_owned()));
+    assert!(start.elapsed() < Duration::from_secs(2));
+
+    // the clients whose connections were closed open new ones
+    for (i, client) in idle.iter().enumerate() {
+        assert_eq!(
+            client.get(format!("key{}", i))?,
+            Some(format!("value{}", i))
+        );
+    }
+    handle.shutdown(Duration::from_secs(1));
+    Ok(())
+}
+
+// A server should be reachable over a Unix domain socket, whose file is removed once the server
+// stops, and should never take over a path that is in use or is not a socket
+#[cfg(unix)]
+#[test]
+fn unix_socket() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let path = temp_dir.path().join("kvs.sock");
+    let addr: KvsAddr = format!("unix:{}", path.display()).parse()?;
+    assert_eq!(addr, KvsAddr::Unix(path.clone()));
+
+    let server = KvsServer::bind(
+        addr.clone(),
+        KvStore::open(temp_dir.path())?,
+        SharedQueueThreadPool::new(2)?,
+    )?;
+    assert_eq!(server.local_addr(), addr);
+    // a socket that is being listened on is not taken over
+    let other = KvsServer::bind(
+        addr.clone(),
+        KvStore::open(temp_dir.path())?,
+        SharedQueueThreadPool::new(1)?,
+    );
+    match other {
+        Err(KvsError::IoError(ref err)) if err.kind() == std::io::ErrorKind::AddrInUse => {}
+        Err(err) => panic!("unexpected error {:?}", err),
+        Ok(_) => panic!("bound a socket that is being listened on"),
+    }
+    // and neither is a file that is not a socket
+    let file = temp_dir.path().join("important.txt");
+    std::fs::write(&file, "important")?;
+    let other = KvsServer::bind(
+        format!("unix:{}", file.display()).parse()?,
+        KvStore::open(temp_dir.path())?,
+        SharedQueueThreadPool::new(1)?,
+    );
+    match other {
+        Err(KvsError::IoError(ref err)) if err.kind() == std::io::ErrorKind::AlreadyExists => {}
+        Err(err) => panic!("unexpected error {:?}", err),
+        Ok(_) => panic!("bound over a regular file"),
+    }
+    assert_eq!(std::fs::read_to_string(&file)?, "important");
+    let handle = server.shutdown_handle();
+    let serving = thread::spawn(move || server.serve());
+
+    let client = KvsClient::new(addr);
+    client.set("key".to_owned(), "value".to_owned())?;
+    assert_eq!(client.get("key".to_owned())?, Some("value".to_owned()));
+
+    handle.shutdown(Duration::from_secs(5));
+    serving.join().expect("server panicked")?;
+    assert!(!path.exists());
+
+    // a socket left behind by a server that is gone is replaced
+    drop(std::os::unix::net::UnixListener::bind(&path)?);
+    assert!(path.exists());
+    KvsServer::bind(
+        format!("unix:{}", path.display()).parse()?,
+        KvStore::open(temp_dir.path())?,
+        SharedQueueThreadPool::new(1)?,
+    )?;
+    Ok(())
+}
@@ -0,0 +1,62 @@
+use kvs::{NaiveThreadPool, RayonThreadPool, Result, SharedQueueThreadPool, ThreadPool};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::Duration;
+
+const JOBS: usize = 64;
+
+// Every spawned job should run exactly once
+fn check_runs_jobs<P: ThreadPool>() -> Result<()> {
+    let pool = P::new(4)?;
+    let counter = Arc::new(AtomicUsize::new(0));
+    let (sender, receiver) = mpsc::channel();
+    for _ in 0..JOBS {
+        let counter = counter.clone();
+        let sender = sender.clone();
+        pool.spawn(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            sender.send(()).unwrap();
+        });
+    }
+    for _ in 0..JOBS {
+        receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+    }
+    assert_eq!(counter.load(Ordering::SeqCst), JOBS);
+    Ok(())
+}
+
+// Jobs that panic should not stop the pool from running the jobs spawned after them, even when
+// every thread has had a job panic on it
+fn check_survives_panics<P: ThreadPool>() -> Result<()> {
+    let pool = P::new(4)?;
+    for _ in 0..8 {
+        pool.spawn(|| panic!("job panicked on purpose"));
+    }
+    let (sender, receiver) = mpsc::channel();
+    for _ in 0..JOBS {
+        let sender = sender.clone();
+        pool.spawn(move || sender.send(()).unwrap());
+    }
+    for _ in 0..JOBS {
+        receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+    }
+    Ok(())
+}
+
+#[test]
+fn naive_thread_pool() -> Result<()> {
+    check_runs_jobs::<NaiveThreadPool>()?;
+    check_survives_panics::<NaiveThreadPool>()
+}
+
+#[test]
+fn shared_queue_thread_pool() -> Result<()> {
+    check_runs_jobs::<SharedQueueThreadPool>()?;
+    check_survives_panics::<SharedQueueThreadPool>()
+}
+
+#[test]
+fn rayon_thread_pool() -> Result<()> {
+    check_runs_jobs::<RayonThreadPool>()?;
+    check_survives_panics::<RayonThreadPool>()
+}